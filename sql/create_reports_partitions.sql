@@ -0,0 +1 @@
+select main.create_reports_partitions($1, $2);
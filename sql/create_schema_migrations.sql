@@ -0,0 +1,8 @@
+create schema if not exists main;
+
+create table if not exists main.schema_migrations
+(
+    version    integer primary key,
+    name       varchar(100) not null,
+    applied_at timestamptz  not null default now()
+);
@@ -0,0 +1,8 @@
+with deleted_tags as (
+    delete
+    from main.report_tags
+    where report_id = any ($1)
+)
+delete
+from main.reports
+where report_id = any ($1);
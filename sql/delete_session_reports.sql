@@ -0,0 +1,14 @@
+with session_reports as (
+    select report_id
+    from main.reports
+    where project_id = $1
+      and session_id = $2
+)
+   , deleted_tags as (
+    delete
+    from main.report_tags
+    where report_id in (select report_id from session_reports)
+)
+delete
+from main.reports
+where report_id in (select report_id from session_reports);
@@ -0,0 +1,3 @@
+delete
+from main.session_clock_skews
+where last_seen < $1;
@@ -0,0 +1,5 @@
+delete
+from main.tag_groups
+where project_id = $1
+  and tag_group_id = $2
+returning tag_group_id;
@@ -0,0 +1,17 @@
+select reports.report_id,
+       reports.project_id,
+       reports.session_id,
+       reports.timestamp,
+       reports.received_at,
+       reports.corrected_timestamp,
+       reports.properties,
+       array(select tags.name
+             from main.report_tags
+             inner join main.tags using (tag_id)
+             where report_tags.report_id = reports.report_id
+             order by tags.name) as tags
+from main.reports
+inner join main.projects using (project_id)
+where projects.retention_days is not null
+  and reports.received_at < $1 - projects.retention_days * 86400000::bigint
+limit $2 for update of reports skip locked;
@@ -0,0 +1,3 @@
+select project_id, ingest_secret_hash, revoked_at is not null as revoked
+from main.projects
+where access_key = $1;
@@ -1,4 +1,4 @@
-select projects.name, projects.access_key
+select projects.project_id, projects.name, projects.access_key, projects.retention_days
 from main.users
 inner join main.memberships using (user_id)
 inner join main.projects using (project_id)
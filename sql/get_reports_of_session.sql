@@ -1,4 +0,0 @@
-select *
-from main.reports
-where session_id = $1
-order by timestamp asc;
\ No newline at end of file
@@ -0,0 +1,2 @@
+select received_until, days_until
+from main.rollup_state;
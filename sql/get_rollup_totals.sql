@@ -0,0 +1,7 @@
+select coalesce(sum(sessions), 0)::bigint     as sessions,
+       coalesce(sum(reports), 0)::bigint      as reports,
+       coalesce(sum(duration_sum), 0)::bigint as duration_sum
+from main.daily_rollups
+where project_id = $1
+  and day_start >= $2
+  and day_start < $3;
@@ -0,0 +1,45 @@
+-- the sessions of the project that started in [$2, $3) by the clock $8, with a report matching
+-- the property filter if there is one, with all their reports like `main.roll_up_day` does
+with started as (
+    select session_id
+    from main.reports
+    where project_id = $1
+      and session_id in (
+        select session_id
+        from main.reports
+        where project_id = $1
+          and ($2::bigint is null or main.report_time(reports, $8) >= $2)
+          and ($3::bigint is null or main.report_time(reports, $8) < $3)
+          and reports.timestamp >= main.timestamp_lower_bound($2, $8)
+          and reports.timestamp < main.timestamp_upper_bound($3, $8)
+    )
+    group by session_id
+    having ($2::bigint is null or min(main.report_time(reports, $8)) >= $2)
+       and ($3::bigint is null or min(main.report_time(reports, $8)) < $3)
+       and ($5::text is null or bool_or(main.property_matches(properties, $5, $6, $7)))
+)
+   , gaps as (
+    select session_id,
+           main.report_time(reports, $8)
+               - lag(main.report_time(reports, $8))
+                 over (partition by session_id order by main.report_time(reports, $8)) as gap
+    from started
+    inner join main.reports using (session_id)
+    where reports.project_id = $1
+)
+   , durations as (
+    -- the gaps add up to the time between the first and the last report
+    select coalesce(sum(gap), 0)                                as duration,
+           coalesce(sum(gap) filter (where gap <= $4::bigint), 0) as active_time,
+           count(gap) filter (where gap > $4::bigint) + 1        as visits
+    from gaps
+    group by session_id
+)
+select coalesce(avg(duration), 0)::bigint                                             as mean,
+       coalesce(percentile_cont(0.5) within group (order by duration), 0)::bigint    as median,
+       coalesce(percentile_cont(0.9) within group (order by duration), 0)::bigint    as p90,
+       coalesce(avg(active_time), 0)::bigint                                          as active_mean,
+       coalesce(percentile_cont(0.5) within group (order by active_time), 0)::bigint as active_median,
+       coalesce(percentile_cont(0.9) within group (order by active_time), 0)::bigint as active_p90,
+       coalesce(sum(visits), 0)::bigint                                               as visits
+from durations;
@@ -1,3 +0,0 @@
-select distinct session_id
-from main.reports
-where project_id = $1;
\ No newline at end of file
@@ -0,0 +1,10 @@
+-- the rolled up days that the sessions started on, before $2
+select distinct project_id, day_start
+from (
+    select project_id, min(timestamp) - min(timestamp) % 86400000 as day_start
+    from main.reports
+    where session_id = any ($1)
+    group by project_id, session_id
+) started
+where day_start >= 0
+  and day_start < $2;
@@ -0,0 +1,21 @@
+-- the sessions of the project that started in [$2, $3) by the clock $7, with a report matching
+-- the property filter if there is one, counted like `main.roll_up_day` does
+select count(*) as count
+from (
+    select session_id
+    from main.reports
+    where project_id = $1
+      and session_id in (
+        select session_id
+        from main.reports
+        where project_id = $1
+          and ($2::bigint is null or main.report_time(reports, $7) >= $2)
+          and ($3::bigint is null or main.report_time(reports, $7) < $3)
+          and reports.timestamp >= main.timestamp_lower_bound($2, $7)
+          and reports.timestamp < main.timestamp_upper_bound($3, $7)
+    )
+    group by session_id
+    having ($2::bigint is null or min(main.report_time(reports, $7)) >= $2)
+       and ($3::bigint is null or min(main.report_time(reports, $7)) < $3)
+       and ($4::text is null or bool_or(main.property_matches(properties, $4, $5, $6)))
+) started;
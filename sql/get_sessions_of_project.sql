@@ -0,0 +1,29 @@
+select reports.session_id,
+       main.report_time(reports, $7) as timestamp,
+       reports.properties,
+       coalesce(array_agg(distinct coalesce(project_tags.display_name, tags.name)
+                          order by coalesce(project_tags.display_name, tags.name))
+                filter (where tags.name is not null), '{}') as tags
+from main.reports
+left join main.report_tags using (report_id)
+left join main.tags using (tag_id)
+left join main.project_tags
+          on project_tags.project_id = reports.project_id
+              and project_tags.tag_id = report_tags.tag_id
+where reports.project_id = $1
+  and ($2::bigint is null or main.report_time(reports, $7) >= $2)
+  and ($3::bigint is null or main.report_time(reports, $7) < $3)
+  and reports.timestamp >= main.timestamp_lower_bound($2, $7)
+  and reports.timestamp < main.timestamp_upper_bound($3, $7)
+  and ($4::text is null or reports.session_id in (
+    select session_id
+    from main.reports
+    where project_id = $1
+      and ($2::bigint is null or main.report_time(reports, $7) >= $2)
+      and ($3::bigint is null or main.report_time(reports, $7) < $3)
+      and reports.timestamp >= main.timestamp_lower_bound($2, $7)
+      and reports.timestamp < main.timestamp_upper_bound($3, $7)
+      and main.property_matches(properties, $4, $5, $6)
+  ))
+group by reports.report_id, reports.timestamp
+order by reports.session_id, main.report_time(reports, $7), reports.report_id;
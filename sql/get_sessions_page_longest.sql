@@ -0,0 +1,47 @@
+with page as (
+    select session_id,
+           max(main.report_time(reports, $10)) - min(main.report_time(reports, $10)) as sort_key
+    from main.reports
+    where project_id = $1
+      and ($2::bigint is null or main.report_time(reports, $10) >= $2)
+      and ($3::bigint is null or main.report_time(reports, $10) < $3)
+      and reports.timestamp >= main.timestamp_lower_bound($2, $10)
+      and reports.timestamp < main.timestamp_upper_bound($3, $10)
+      and ($7::text is null or session_id in (
+        select session_id
+        from main.reports
+        where project_id = $1
+          and ($2::bigint is null or main.report_time(reports, $10) >= $2)
+          and ($3::bigint is null or main.report_time(reports, $10) < $3)
+          and reports.timestamp >= main.timestamp_lower_bound($2, $10)
+          and reports.timestamp < main.timestamp_upper_bound($3, $10)
+          and main.property_matches(properties, $7, $8, $9)
+      ))
+    group by session_id
+    having $4::bigint is null
+        or (max(main.report_time(reports, $10)) - min(main.report_time(reports, $10)), session_id)
+               < ($4, $5::uuid)
+    order by sort_key desc, session_id desc
+    limit $6
+)
+select reports.session_id,
+       main.report_time(reports, $10) as timestamp,
+       reports.properties,
+       coalesce(array_agg(distinct coalesce(project_tags.display_name, tags.name)
+                          order by coalesce(project_tags.display_name, tags.name))
+                filter (where tags.name is not null), '{}') as tags
+from page
+inner join main.reports using (session_id)
+left join main.report_tags using (report_id)
+left join main.tags using (tag_id)
+left join main.project_tags
+          on project_tags.project_id = reports.project_id
+              and project_tags.tag_id = report_tags.tag_id
+where reports.project_id = $1
+  and ($2::bigint is null or main.report_time(reports, $10) >= $2)
+  and ($3::bigint is null or main.report_time(reports, $10) < $3)
+  and reports.timestamp >= main.timestamp_lower_bound($2, $10)
+  and reports.timestamp < main.timestamp_upper_bound($3, $10)
+group by reports.report_id, reports.timestamp, page.sort_key
+order by page.sort_key desc, reports.session_id desc, main.report_time(reports, $10),
+         reports.report_id;
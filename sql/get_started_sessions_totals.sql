@@ -0,0 +1,21 @@
+-- the sessions of the project that started in [$2, $3) by the client's clock, counted like
+-- `main.roll_up_day` does: with all their reports and from their first to their last one
+select count(*)::bigint                       as sessions,
+       coalesce(sum(report_count), 0)::bigint as reports,
+       coalesce(sum(duration), 0)::bigint     as duration_sum
+from (
+    select max(reports.timestamp) - min(reports.timestamp) as duration,
+           count(*)                                        as report_count
+    from main.reports
+    where reports.project_id = $1
+      and reports.session_id in (
+        select session_id
+        from main.reports
+        where project_id = $1
+          and timestamp >= main.timestamp_lower_bound($2, false)
+          and timestamp < main.timestamp_upper_bound($3, false)
+    )
+    group by reports.session_id
+    having min(reports.timestamp) >= main.timestamp_lower_bound($2, false)
+       and min(reports.timestamp) < main.timestamp_upper_bound($3, false)
+) started;
@@ -0,0 +1,4 @@
+select tag_group_id, name, description, tags_names, rule
+from main.tag_groups
+where project_id = $1
+order by tag_group_id;
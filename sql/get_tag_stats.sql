@@ -0,0 +1,80 @@
+-- the tags of the project under their display names, hidden ones only with $3: from the rollups
+-- of the days before $4 and from the reports of the sessions that started since, or before the
+-- first rolled up day. The sessions of merged tags are counted from the reports, the rollups
+-- don't tell which sessions two tags share.
+with recent_sessions as (
+    select session_id
+    from main.reports
+    where project_id = $1
+      and session_id in (
+        select session_id
+        from main.reports
+        where project_id = $1
+          and (timestamp >= $4 or timestamp < 0)
+    )
+    group by session_id
+    having min(timestamp) >= $4
+        or min(timestamp) < 0
+),
+     tag_counts as (
+         select tag_id, sessions, reports, first_seen, last_seen
+         from main.daily_tag_counts
+         where project_id = $1
+           and day_start >= 0
+           and day_start < $4
+         union all
+         select report_tags.tag_id,
+                count(distinct reports.session_id),
+                count(*),
+                min(reports.timestamp),
+                max(reports.timestamp)
+         from recent_sessions
+         inner join main.reports using (session_id)
+         inner join main.report_tags using (report_id)
+         where reports.project_id = $1
+         group by report_tags.tag_id
+     ),
+     session_count as (
+         select (select coalesce(sum(sessions), 0)
+                 from main.daily_rollups
+                 where project_id = $1
+                   and day_start >= 0
+                   and day_start < $4)
+                    + (select count(*) from recent_sessions) as sessions
+     ),
+     named_tags as (
+         select coalesce(project_tags.display_name, tags.name)  as name,
+                array_agg(distinct tag_counts.tag_id)           as tag_ids,
+                sum(tag_counts.reports)::bigint                 as occurrences,
+                sum(tag_counts.sessions)::bigint                as sessions,
+                min(tag_counts.first_seen)                      as first_seen,
+                max(tag_counts.last_seen)                       as last_seen,
+                bool_or(coalesce(project_tags.hidden, false))   as hidden
+         from tag_counts
+         inner join main.tags using (tag_id)
+         left join main.project_tags
+                   on project_tags.project_id = $1
+                       and project_tags.tag_id = tag_counts.tag_id
+         where ($2::text is null or left(coalesce(project_tags.display_name, tags.name), length($2)) = $2)
+           and ($3 or not coalesce(project_tags.hidden, false))
+         group by 1
+     )
+select named_tags.name,
+       named_tags.occurrences,
+       merged.sessions,
+       named_tags.first_seen,
+       named_tags.last_seen,
+       named_tags.hidden,
+       least(merged.sessions::double precision
+                 / greatest((select sessions from session_count), 1), 1) as session_share
+from named_tags
+cross join lateral (
+    select case
+               when cardinality(named_tags.tag_ids) = 1 then named_tags.sessions
+               else (select count(distinct reports.session_id)
+                     from main.report_tags
+                     inner join main.reports using (report_id)
+                     where report_tags.tag_id = any (named_tags.tag_ids)
+                       and reports.project_id = $1)
+               end as sessions
+) as merged;
@@ -0,0 +1,21 @@
+-- the tags of rolled up days, and of the reports since, as the project shows them: under their
+-- display names, without the hidden ones
+select coalesce(project_tags.display_name, tags.name) as name
+from (
+         select tag_id
+         from main.daily_tag_counts
+         where project_id = $1
+         union
+         select report_tags.tag_id
+         from main.reports
+         inner join main.report_tags using (report_id)
+         where reports.project_id = $1
+           and reports.timestamp >= $2
+     ) as project_tag_ids
+inner join main.tags using (tag_id)
+left join main.project_tags
+          on project_tags.project_id = $1
+              and project_tags.tag_id = project_tag_ids.tag_id
+group by 1
+having not bool_or(coalesce(project_tags.hidden, false))
+order by name;
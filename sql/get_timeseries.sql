@@ -0,0 +1,39 @@
+-- $4 is the interval (`hour`, `day` or `week`), $5 the time zone the buckets start in.
+-- $6 is the metric: `sessions` counts the distinct sessions with reports in a bucket, `reports`
+-- and `tag` the reports, of tag $7 for `tag` as the project shows it.
+-- Buckets are truncated in the time zone but kept as instants, so that the hours repeated or
+-- skipped by daylight saving time are buckets of their own or none. $8 steps through the range
+-- finely enough to hit every bucket, even the shorter ones.
+with buckets as (
+    select distinct date_trunc($4::text, instant, $5::text) as bucket
+    from generate_series(
+                 date_trunc($4::text, to_timestamp($2::bigint / 1000.0), $5::text),
+                 to_timestamp(($3::bigint - 1) / 1000.0),
+                 $8::text::interval) instant
+),
+     counts as (
+         select date_trunc($4, to_timestamp(reports.timestamp / 1000.0), $5) as bucket,
+                case
+                    when $6 = 'sessions' then count(distinct reports.session_id)
+                    else count(*)
+                    end                                                      as count
+         from main.reports
+         where reports.project_id = $1
+           and reports.timestamp >= $2
+           and reports.timestamp < $3
+           and ($6 <> 'tag' or exists(
+                 select
+                 from main.report_tags
+                          inner join main.tags using (tag_id)
+                          left join main.project_tags
+                                    on project_tags.project_id = reports.project_id
+                                        and project_tags.tag_id = report_tags.tag_id
+                 where report_tags.report_id = reports.report_id
+                   and coalesce(project_tags.display_name, tags.name) = $7))
+         group by 1
+     )
+select (extract(epoch from buckets.bucket) * 1000)::bigint as start,
+       coalesce(counts.count, 0)::bigint                  as count
+from buckets
+         left join counts using (bucket)
+order by buckets.bucket;
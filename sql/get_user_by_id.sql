@@ -1,2 +1,2 @@
 select user_id, username, email, password from main.users
-where username = $1;
\ No newline at end of file
+where user_id = $1;
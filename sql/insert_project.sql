@@ -1,6 +1,6 @@
 with inserted_project as (
     insert into main.projects (name)
-        values ($1) returning project_id, name, access_key
+        values ($1) returning project_id, name, access_key, retention_days
 )
    , inserted_membership as (
     insert
@@ -9,5 +9,5 @@ with inserted_project as (
             from inserted_project
             returning project_id
 )
-select inserted_project.name, inserted_project.access_key
+select inserted_project.project_id, inserted_project.name, inserted_project.access_key, inserted_project.retention_days
 from inserted_project
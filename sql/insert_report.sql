@@ -1,8 +1,4 @@
-with project as (
-    select project_id from main.projects
-    where access_key = $1
-)
-insert into main.reports (project_id, session_id, timestamp)
-select project.project_id, $2, $3
-from project
-returning report_id;
\ No newline at end of file
+insert into main.reports (project_id, session_id, timestamp, properties, received_at,
+                          corrected_timestamp)
+values ($1, $2, $3, $4, $5, $6)
+returning report_id;
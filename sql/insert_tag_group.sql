@@ -0,0 +1,4 @@
+insert into main.tag_groups (project_id, name, description, tags_names, rule)
+values ($1, $2, $3, $4, $5)
+on conflict (project_id, name) do nothing
+returning tag_group_id, name, description, tags_names, rule;
@@ -0,0 +1,4 @@
+insert into main.users (username, email, password)
+values ($1, $2, $3)
+on conflict (email) do nothing
+returning user_id, username, email, password;
@@ -0,0 +1,4 @@
+select exists(
+    select 1 from main.memberships
+    where user_id = $1 and project_id = $2
+) as is_member;
@@ -0,0 +1,3 @@
+select received_until, days_until
+from main.rollup_state
+for update skip locked;
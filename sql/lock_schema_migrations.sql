@@ -0,0 +1 @@
+lock table main.schema_migrations in exclusive mode;
@@ -1,3 +1,7 @@
+-- The schema from before migrations, `if not exists` adopts databases created by hand.
+create schema if not exists main;
+create extension if not exists pgcrypto;
+
 create table if not exists main.users
 (
     user_id  serial primary key,
@@ -9,7 +13,7 @@ create table if not exists main.users
 create table if not exists main.projects
 (
     project_id serial primary key,
-    access_key uuid not null unique default main.gen_random_uuid(),
+    access_key uuid not null unique default gen_random_uuid(),
     name       varchar(20)
 );
 
@@ -25,9 +29,9 @@ create table if not exists main.memberships
 create table if not exists main.reports
 (
     report_id  serial primary key,
-    project_id integer     not null references main.projects (project_id),
-    session_id uuid not null,
-    timestamp  bigint   not null default current_timestamp
+    project_id integer not null references main.projects (project_id),
+    session_id uuid    not null,
+    timestamp  bigint  not null
 );
 
 create table if not exists main.tags
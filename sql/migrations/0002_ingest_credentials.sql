@@ -0,0 +1,3 @@
+-- when set, reports must send it in the X-Ingest-Secret header
+alter table main.projects add column if not exists ingest_secret varchar(64);
+alter table main.projects add column if not exists revoked_at timestamptz;
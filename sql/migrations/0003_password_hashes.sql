@@ -0,0 +1,2 @@
+-- argon2 hashes, rows created before hashing are rehashed on the next login
+alter table main.users alter column password type varchar(255);
@@ -0,0 +1,9 @@
+create table if not exists main.tag_groups
+(
+    tag_group_id serial primary key,
+    project_id   integer       not null references main.projects (project_id),
+    name         varchar(50)   not null,
+    description  text          not null default '',
+    tags_names   varchar(20)[] not null default '{}',
+    unique (project_id, name)
+);
@@ -0,0 +1,2 @@
+alter table main.tag_groups
+    add column if not exists rule jsonb;
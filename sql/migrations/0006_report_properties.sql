@@ -0,0 +1,25 @@
+alter table main.reports
+    add column if not exists properties jsonb not null default '{}';
+
+alter table main.tags
+    alter column name type varchar(100);
+
+alter table main.tag_groups
+    alter column tags_names type varchar(100)[];
+
+-- Whether a report's `property` passes the filter `op` against `value`, shared by the session
+-- queries. Values are compared as text, so `42` equals the number 42 and the string "42".
+create or replace function main.property_matches(properties jsonb, property text, op text, value text)
+    returns boolean
+    language sql
+    immutable
+as
+$$
+select case op
+           when 'exists' then properties ? property
+           when 'equals' then properties ->> property = value
+           when 'starts_with' then strpos(properties ->> property, value) = 1
+           when 'contains' then strpos(properties ->> property, value) > 0
+           else false
+           end
+$$;
@@ -0,0 +1,44 @@
+-- `timestamp` is the client's time. The old schema gave it a `current_timestamp` default,
+-- which doesn't fit a bigint, inserts always set it.
+alter table main.reports
+    alter column timestamp drop default;
+
+alter table main.reports
+    add column if not exists received_at bigint;
+
+alter table main.reports
+    add column if not exists corrected_timestamp bigint;
+
+-- nothing is known about the clocks of the reports saved so far
+update main.reports
+set received_at         = timestamp,
+    corrected_timestamp = timestamp
+where received_at is null;
+
+alter table main.reports
+    alter column received_at set not null,
+    alter column received_at set default (extract(epoch from clock_timestamp()) * 1000)::bigint,
+    alter column corrected_timestamp set not null;
+
+-- The time of a report by the client's clock, or corrected by its session's clock skew.
+create or replace function main.report_time(report main.reports, corrected boolean)
+    returns bigint
+    language sql
+    immutable
+as
+$$
+select case when corrected then report.corrected_timestamp else report.timestamp end
+$$;
+
+-- The clock skew of a session, estimated from its first batch of reports and kept so that all
+-- of its reports are corrected alike. Skews unused for longer than reports are accepted late
+-- are deleted.
+create table if not exists main.session_clock_skews
+(
+    session_id uuid primary key,
+    clock_skew bigint not null,
+    last_seen  bigint not null
+);
+
+create index if not exists session_clock_skews_last_seen_idx
+    on main.session_clock_skews (last_seen);
@@ -0,0 +1,12 @@
+-- Reports received more than `retention_days` ago are deleted, null keeps them forever.
+alter table main.projects
+    add column if not exists retention_days integer check (retention_days > 0);
+
+create index if not exists reports_project_id_timestamp_idx
+    on main.reports (project_id, timestamp);
+
+create index if not exists reports_session_id_idx
+    on main.reports (session_id);
+
+create index if not exists reports_received_at_idx
+    on main.reports (received_at);
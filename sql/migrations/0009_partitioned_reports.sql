@@ -0,0 +1,134 @@
+-- `main.reports` becomes partitioned by month of `timestamp`. Foreign keys to a partitioned
+-- table must include the partition key, so `report_tags` no longer references it, reports
+-- and their tags are deleted together by the server.
+alter table main.report_tags
+    drop constraint if exists report_tags_report_id_fkey;
+
+drop function if exists main.report_time(main.reports, boolean);
+
+drop index if exists main.reports_project_id_timestamp_idx;
+drop index if exists main.reports_session_id_idx;
+drop index if exists main.reports_received_at_idx;
+
+alter table main.reports
+    rename to reports_unpartitioned;
+alter table main.reports_unpartitioned
+    rename constraint reports_pkey to reports_unpartitioned_pkey;
+
+create table main.reports
+(
+    report_id           integer not null default nextval('main.reports_report_id_seq'),
+    project_id          integer not null references main.projects (project_id),
+    session_id          uuid    not null,
+    timestamp           bigint  not null,
+    properties          jsonb   not null default '{}',
+    received_at         bigint  not null default (extract(epoch from clock_timestamp()) * 1000)::bigint,
+    corrected_timestamp bigint  not null,
+    primary key (report_id, timestamp)
+) partition by range (timestamp);
+
+alter sequence main.reports_report_id_seq owned by main.reports.report_id;
+
+-- reports outside of the monthly partitions, from clients with clocks far off
+create table main.reports_default partition of main.reports default;
+
+create index reports_project_id_timestamp_idx on main.reports (project_id, timestamp);
+create index reports_session_id_timestamp_idx on main.reports (session_id, timestamp);
+create index reports_received_at_idx on main.reports (received_at);
+
+-- Creates the partition of the month of `month_start`, moving its reports out of the default
+-- partition. Does nothing if it exists.
+create or replace function main.create_reports_partition(month_start date)
+    returns void
+    language plpgsql
+as
+$$
+declare
+    month          date   := date_trunc('month', month_start)::date;
+    partition_name text   := format('reports_%s', to_char(month, 'YYYY_MM'));
+    from_ms        bigint := (extract(epoch from month::timestamp at time zone 'UTC') * 1000)::bigint;
+    to_ms          bigint := (extract(epoch from (month + interval '1 month') at time zone 'UTC') * 1000)::bigint;
+begin
+    if to_regclass(format('main.%I', partition_name)) is not null then
+        return;
+    end if;
+
+    execute format('create table main.%I (like main.reports including defaults)', partition_name);
+    execute format('with moved as (delete from main.reports_default'
+                       ' where timestamp >= %s and timestamp < %s returning *)'
+                       ' insert into main.%I select * from moved',
+                   from_ms, to_ms, partition_name);
+    execute format('alter table main.reports attach partition main.%I for values from (%s) to (%s)',
+                   partition_name, from_ms, to_ms);
+end
+$$;
+
+-- Creates the partitions from the month of `now_ms` to `months_ahead` months later.
+create or replace function main.create_reports_partitions(now_ms bigint, months_ahead integer)
+    returns void
+    language plpgsql
+as
+$$
+begin
+    for i in 0..months_ahead
+        loop
+            perform main.create_reports_partition(
+                    (to_timestamp(now_ms / 1000.0) at time zone 'UTC' + make_interval(months => i))::date);
+        end loop;
+end
+$$;
+
+-- partitions for the months reports were sent in, a clock far off can't create one
+do
+$$
+    declare
+        month date;
+    begin
+        for month in
+            select distinct date_trunc('month', to_timestamp(timestamp / 1000.0) at time zone 'UTC')::date
+            from main.reports_unpartitioned
+            where timestamp >= 0
+              and timestamp < extract(epoch from now() + interval '1 month') * 1000
+            loop
+                perform main.create_reports_partition(month);
+            end loop;
+    end
+$$;
+
+insert into main.reports (report_id, project_id, session_id, timestamp, properties, received_at,
+                          corrected_timestamp)
+select report_id, project_id, session_id, timestamp, properties, received_at, corrected_timestamp
+from main.reports_unpartitioned;
+
+drop table main.reports_unpartitioned;
+
+-- The time of a report by the client's clock, or corrected by its session's clock skew.
+create or replace function main.report_time(report main.reports, corrected boolean)
+    returns bigint
+    language sql
+    immutable
+as
+$$
+select case when corrected then report.corrected_timestamp else report.timestamp end
+$$;
+
+-- The range of `timestamp` that can hold reports sent in [from_ms, to_ms) by the chosen clock.
+-- Queries compare the partition key to them so that partitions out of the range are skipped,
+-- any partition can hold reports by the corrected clock.
+create or replace function main.timestamp_lower_bound(from_ms bigint, corrected boolean)
+    returns bigint
+    language sql
+    immutable
+as
+$$
+select case when corrected or from_ms is null then '-9223372036854775808'::bigint else from_ms end
+$$;
+
+create or replace function main.timestamp_upper_bound(to_ms bigint, corrected boolean)
+    returns bigint
+    language sql
+    immutable
+as
+$$
+select case when corrected or to_ms is null then '9223372036854775807'::bigint else to_ms end
+$$;
@@ -0,0 +1,104 @@
+-- Per-project aggregates of the sessions that started on a day (UTC, by the client's clock),
+-- recomputed when reports of those sessions arrive. Days start at `day_start` millisecs.
+create table if not exists main.daily_rollups
+(
+    project_id         integer  not null references main.projects (project_id),
+    day_start          bigint   not null,
+    sessions           bigint   not null,
+    reports            bigint   not null,
+    duration_sum       bigint   not null,
+    -- sessions by duration, split at `main.duration_histogram_bounds()`
+    duration_histogram bigint[] not null,
+    primary key (project_id, day_start)
+);
+
+create table if not exists main.daily_tag_counts
+(
+    project_id integer not null references main.projects (project_id),
+    day_start  bigint  not null,
+    tag_id     integer not null references main.tags (tag_id),
+    sessions   bigint  not null,
+    reports    bigint  not null,
+    primary key (project_id, day_start, tag_id)
+);
+
+-- Reports received before `received_until` are rolled up, and so are the days before
+-- `days_until`. A single row, locked while the rollups are computed.
+create table if not exists main.rollup_state
+(
+    id             boolean primary key default true check (id),
+    received_until bigint not null,
+    days_until     bigint not null
+);
+
+insert into main.rollup_state (received_until, days_until)
+values (0, 0)
+on conflict do nothing;
+
+-- In millisecs: 10s, 30s, 1m, 3m, 10m, 30m and 1h.
+create or replace function main.duration_histogram_bounds()
+    returns bigint[]
+    language sql
+    immutable
+as
+$$
+select array [10000, 30000, 60000, 180000, 600000, 1800000, 3600000]::bigint[]
+$$;
+
+-- Recomputes the rollups of the sessions of the project that started in the day.
+create or replace function main.roll_up_day(project integer, day bigint)
+    returns void
+    language plpgsql
+as
+$$
+begin
+    delete from main.daily_tag_counts where project_id = project and day_start = day;
+
+    create temporary table day_sessions on commit drop as
+    select reports.session_id,
+           max(reports.timestamp) - min(reports.timestamp) as duration,
+           count(*)                                        as report_count
+    from main.reports
+    where reports.project_id = project
+      and reports.session_id in (
+        select session_id
+        from main.reports
+        where project_id = project
+          and timestamp >= day
+          and timestamp < day + 86400000
+    )
+    group by reports.session_id
+    having min(reports.timestamp) >= day
+       and min(reports.timestamp) < day + 86400000;
+
+    insert into main.daily_rollups (project_id, day_start, sessions, reports, duration_sum,
+                                    duration_histogram)
+    select project,
+           day,
+           count(*),
+           coalesce(sum(day_sessions.report_count), 0),
+           coalesce(sum(day_sessions.duration), 0),
+           array(select count(day_sessions.session_id)
+                 from generate_series(0, array_length(main.duration_histogram_bounds(), 1)) bucket
+                 left join day_sessions
+                           on width_bucket(day_sessions.duration, main.duration_histogram_bounds()) = bucket
+                 group by bucket
+                 order by bucket)
+    from day_sessions
+    on conflict (project_id, day_start) do update
+        set sessions           = excluded.sessions,
+            reports            = excluded.reports,
+            duration_sum       = excluded.duration_sum,
+            duration_histogram = excluded.duration_histogram;
+
+    insert into main.daily_tag_counts (project_id, day_start, tag_id, sessions, reports)
+    select project, day, report_tags.tag_id, count(distinct reports.session_id), count(*)
+    from day_sessions
+    inner join main.reports using (session_id)
+    inner join main.report_tags using (report_id)
+    where reports.project_id = project
+    group by report_tags.tag_id;
+
+    drop table day_sessions;
+end
+$$;
@@ -0,0 +1,89 @@
+-- How a project shows the tags of its reports, without changing them for other projects. Tags
+-- given the same `display_name` are merged, hidden tags are left out of the project's tag
+-- statistics. Sessions keep the original names, which tag groups match.
+create table if not exists main.project_tags
+(
+    project_id   integer      not null references main.projects (project_id),
+    tag_id       integer      not null references main.tags (tag_id),
+    display_name varchar(100),
+    hidden       boolean      not null default false,
+    primary key (project_id, tag_id)
+);
+
+-- When the tags of a day's sessions were first and last seen, so that the tag statistics of a
+-- project can be served from the rollups.
+alter table main.daily_tag_counts
+    add column if not exists first_seen bigint,
+    add column if not exists last_seen  bigint;
+
+-- Recomputes the rollups of the sessions of the project that started in the day.
+create or replace function main.roll_up_day(project integer, day bigint)
+    returns void
+    language plpgsql
+as
+$$
+begin
+    delete from main.daily_tag_counts where project_id = project and day_start = day;
+
+    create temporary table day_sessions on commit drop as
+    select reports.session_id,
+           max(reports.timestamp) - min(reports.timestamp) as duration,
+           count(*)                                        as report_count
+    from main.reports
+    where reports.project_id = project
+      and reports.session_id in (
+        select session_id
+        from main.reports
+        where project_id = project
+          and timestamp >= day
+          and timestamp < day + 86400000
+    )
+    group by reports.session_id
+    having min(reports.timestamp) >= day
+       and min(reports.timestamp) < day + 86400000;
+
+    insert into main.daily_rollups (project_id, day_start, sessions, reports, duration_sum,
+                                    duration_histogram)
+    select project,
+           day,
+           count(*),
+           coalesce(sum(day_sessions.report_count), 0),
+           coalesce(sum(day_sessions.duration), 0),
+           array(select count(day_sessions.session_id)
+                 from generate_series(0, array_length(main.duration_histogram_bounds(), 1)) bucket
+                 left join day_sessions
+                           on width_bucket(day_sessions.duration, main.duration_histogram_bounds()) = bucket
+                 group by bucket
+                 order by bucket)
+    from day_sessions
+    on conflict (project_id, day_start) do update
+        set sessions           = excluded.sessions,
+            reports            = excluded.reports,
+            duration_sum       = excluded.duration_sum,
+            duration_histogram = excluded.duration_histogram;
+
+    insert into main.daily_tag_counts (project_id, day_start, tag_id, sessions, reports, first_seen,
+                                       last_seen)
+    select project,
+           day,
+           report_tags.tag_id,
+           count(distinct reports.session_id),
+           count(*),
+           min(reports.timestamp),
+           max(reports.timestamp)
+    from day_sessions
+    inner join main.reports using (session_id)
+    inner join main.report_tags using (report_id)
+    where reports.project_id = project
+    group by report_tags.tag_id;
+
+    drop table day_sessions;
+end
+$$;
+
+select main.roll_up_day(project_id, day_start)
+from main.daily_rollups;
+
+alter table main.daily_tag_counts
+    alter column first_seen set not null,
+    alter column last_seen set not null;
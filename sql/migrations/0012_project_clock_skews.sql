@@ -0,0 +1,20 @@
+-- Clock skews belong to a session of a project, so that reports sent with the key of one
+-- project can't set the skew of a session of another. Skews of sessions without reports are
+-- dropped, they are estimated again from the next batch.
+alter table main.session_clock_skews
+    drop constraint if exists session_clock_skews_pkey,
+    add column if not exists project_id integer references main.projects (project_id);
+
+insert into main.session_clock_skews (project_id, session_id, clock_skew, last_seen)
+select distinct reports.project_id, skews.session_id, skews.clock_skew, skews.last_seen
+from main.session_clock_skews as skews
+         inner join main.reports on reports.session_id = skews.session_id
+where skews.project_id is null;
+
+delete
+from main.session_clock_skews
+where project_id is null;
+
+alter table main.session_clock_skews
+    alter column project_id set not null,
+    add primary key (project_id, session_id);
@@ -0,0 +1,4 @@
+-- Finds the reports of a tag, to rename the tags a project has and to count the sessions of
+-- merged tags.
+create index if not exists report_tags_tag_id_idx
+    on main.report_tags (tag_id);
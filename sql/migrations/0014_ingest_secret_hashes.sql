@@ -0,0 +1,8 @@
+-- Projects keep the SHA-256 of their ingest secret, hex encoded, so that the secrets can't be
+-- read from the database.
+alter table main.projects
+    rename column ingest_secret to ingest_secret_hash;
+
+update main.projects
+set ingest_secret_hash = encode(sha256(convert_to(ingest_secret_hash, 'UTF8')), 'hex')
+where ingest_secret_hash is not null;
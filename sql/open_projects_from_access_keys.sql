@@ -0,0 +1,4 @@
+select project_id, access_key from main.projects
+where access_key = any($1)
+  and revoked_at is null
+  and ingest_secret_hash is null;
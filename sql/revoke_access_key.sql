@@ -0,0 +1,3 @@
+update main.projects
+set revoked_at = now()
+where project_id = $1;
@@ -0,0 +1 @@
+select main.roll_up_day($1, $2);
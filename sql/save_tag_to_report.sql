@@ -1,2 +0,0 @@
-insert into main.report_tags (report_id, tag_id)
-values ($1, $2);
\ No newline at end of file
@@ -0,0 +1,3 @@
+insert into main.report_tags (report_id, tag_id)
+select $1, unnest($2::integer[])
+on conflict do nothing;
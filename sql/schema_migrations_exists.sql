@@ -0,0 +1 @@
+select to_regclass('main.schema_migrations') is not null as exists;
@@ -0,0 +1,3 @@
+update main.projects
+set ingest_secret_hash = $2
+where project_id = $1;
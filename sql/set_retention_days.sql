@@ -0,0 +1,3 @@
+update main.projects
+set retention_days = $2
+where project_id = $1;
@@ -0,0 +1,3 @@
+update main.users
+set password = $2
+where user_id = $1;
@@ -0,0 +1,20 @@
+-- renames ($3) or hides ($4) the tags of the project's reports shown as one of $2 in the
+-- project, keeping what's unset
+insert into main.project_tags (project_id, tag_id, display_name, hidden)
+select $1,
+       tags.tag_id,
+       case when $3::varchar is null then project_tags.display_name else nullif($3, tags.name) end,
+       coalesce($4, project_tags.hidden, false)
+from main.tags
+left join main.project_tags
+          on project_tags.project_id = $1
+              and project_tags.tag_id = tags.tag_id
+where coalesce(project_tags.display_name, tags.name) = any ($2::varchar[])
+  and exists(select
+             from main.report_tags
+             inner join main.reports using (report_id)
+             where report_tags.tag_id = tags.tag_id
+               and reports.project_id = $1)
+on conflict (project_id, tag_id) do update
+    set display_name = excluded.display_name,
+        hidden       = excluded.hidden;
@@ -0,0 +1,3 @@
+update main.rollup_state
+set received_until = $1,
+    days_until     = $2;
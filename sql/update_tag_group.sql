@@ -0,0 +1,8 @@
+update main.tag_groups
+set name        = $3,
+    description = $4,
+    tags_names  = $5,
+    rule        = $6
+where project_id = $1
+  and tag_group_id = $2
+returning tag_group_id, name, description, tags_names, rule;
@@ -0,0 +1,8 @@
+-- keeps the skews already stored, and returns them with the new ones
+insert into main.session_clock_skews (project_id, session_id, clock_skew, last_seen)
+select project_id, session_id, clock_skew, $4
+from unnest($1::integer[], $2::uuid[], $3::bigint[]) as estimates (project_id, session_id, clock_skew)
+order by project_id, session_id
+on conflict (project_id, session_id) do update
+    set last_seen = excluded.last_seen
+returning project_id, session_id, clock_skew;
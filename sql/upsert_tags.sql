@@ -0,0 +1,9 @@
+-- The no-op update makes every tag come back, including those a concurrent batch just
+-- inserted. Tags are locked in name order so that two batches can't deadlock.
+insert into main.tags (name)
+select distinct name
+from unnest($1::varchar[]) as name
+order by name
+on conflict (name) do update
+    set name = excluded.name
+returning tag_id, name;
@@ -1,5 +1,7 @@
 pub mod projects;
 pub mod report_auth;
 pub mod reports;
+pub mod tag_groups;
+pub mod tags;
 pub mod user_auth;
 pub mod users;
@@ -1,9 +1,16 @@
+use crate::api::user_auth;
 use crate::db::projects::Project;
+use crate::db::rollups;
+use crate::db::sessions::{Session, SessionFilter};
 use crate::dberror;
 use actix_identity::Identity;
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 use deadpool_postgres::{Client, Pool};
-use crate::db::sessions::Session;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Deserialize;
+
+const INGEST_SECRET_LEN: usize = 32;
 
 pub async fn get_projects(
     _req: HttpRequest,
@@ -12,7 +19,7 @@ pub async fn get_projects(
 ) -> Result<HttpResponse, Error> {
     let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
 
-    let user_id: i32 = id.identity().unwrap().parse().unwrap();
+    let user_id = user_auth::user_id(&id)?;
 
     let projects = Project::get_projects_of_user(&client, user_id).await?;
     let projects_serialized = serde_json::to_string(&projects)?;
@@ -30,58 +37,171 @@ pub async fn save_project(
 ) -> Result<Project, Error> {
     let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
 
-    let user_id: i32 = id.identity().unwrap().parse().unwrap();
+    let user_id = user_auth::user_id(&id)?;
 
     Ok(Project::save_project(&client, user_id, path.into_inner()).await?)
 }
 
+/// Resolves the project of `access_key`, making sure the logged-in user is one of its members.
+async fn get_member_project_id(
+    client: &Client,
+    id: &Identity,
+    access_key: uuid::Uuid,
+) -> Result<i32, dberror::DataError> {
+    let user_id = user_auth::user_id(id)?;
+    let project_id = Project::get_project_id_from_access_key(client, access_key).await?;
+    Project::check_membership(client, user_id, project_id).await?;
+
+    Ok(project_id)
+}
 
 pub async fn get_project_sessions_count(
     _req: HttpRequest,
-    path: web::Path<String>,
+    id: Identity,
+    path: web::Path<uuid::Uuid>,
+    filter: web::Query<SessionFilter>,
     db_pool: web::Data<Pool>,
 ) -> Result<HttpResponse, Error> {
     let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
 
-    let project_id: i32 =
-        Project::get_project_id_from_access_key(&client, path.into_inner().parse().unwrap())
-            .await?;
+    let project_id = get_member_project_id(&client, &id, path.into_inner()).await?;
 
-    let count = Session::get_sessions_count(&client, project_id).await?;
+    let count = rollups::get_sessions_count(&client, project_id, &filter).await?;
 
     Ok(HttpResponse::Ok()
         .content_type("application/json")
         .body(serde_json::to_string(&count)?))
 }
 
+/// Returns the names of the tags of the project's reports as the project shows them, what tag
+/// groups match. Hidden tags are left out.
 pub async fn get_project_tags(
     _req: HttpRequest,
-    path: web::Path<String>,
+    id: Identity,
+    path: web::Path<uuid::Uuid>,
     db_pool: web::Data<Pool>,
 ) -> Result<HttpResponse, Error> {
     let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
 
-    let tag_names = Project::get_tags(&client, path.into_inner().parse().unwrap()).await?;
+    let project_id = get_member_project_id(&client, &id, path.into_inner()).await?;
+
+    let tag_names = rollups::get_tags(&client, project_id).await?;
 
     Ok(HttpResponse::Ok()
         .content_type("application/json")
         .body(serde_json::to_string(&tag_names)?))
 }
 
+/// Returns the mean session duration in seconds.
 pub async fn get_average_session_duration(
     _req: HttpRequest,
-    path: web::Path<String>,
+    id: Identity,
+    path: web::Path<uuid::Uuid>,
+    filter: web::Query<SessionFilter>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let project_id = get_member_project_id(&client, &id, path.into_inner()).await?;
+
+    let mean_duration = rollups::get_mean_session_duration(&client, project_id, &filter).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&(mean_duration / 1000))?))
+}
+
+/// Returns the mean, median and 90th percentile of session durations in millisecs.
+pub async fn get_session_duration_stats(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<uuid::Uuid>,
+    filter: web::Query<SessionFilter>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let project_id = get_member_project_id(&client, &id, path.into_inner()).await?;
+
+    let duration_stats = Session::get_duration_stats(&client, project_id, &filter).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&duration_stats)?))
+}
+
+/// Generates a new ingest secret for the project, which reports must then send in the
+/// `X-Ingest-Secret` header. The secret is only shown once.
+pub async fn rotate_ingest_secret(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<uuid::Uuid>,
     db_pool: web::Data<Pool>,
 ) -> Result<HttpResponse, Error> {
     let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
 
-    let project_id: i32 =
-        Project::get_project_id_from_access_key(&client, path.into_inner().parse().unwrap())
-            .await?;
+    let project_id = get_member_project_id(&client, &id, path.into_inner()).await?;
 
-    let avg_duration = Session::get_average_session_duration(&client, project_id).await?;
+    let ingest_secret = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(INGEST_SECRET_LEN)
+        .collect::<String>();
+    Project::set_ingest_secret(&client, project_id, Some(&ingest_secret)).await?;
 
     Ok(HttpResponse::Ok()
         .content_type("application/json")
-        .body(serde_json::to_string(&avg_duration.as_secs())?))
-}
\ No newline at end of file
+        .body(serde_json::to_string(&ingest_secret)?))
+}
+
+pub async fn remove_ingest_secret(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<uuid::Uuid>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let project_id = get_member_project_id(&client, &id, path.into_inner()).await?;
+
+    Project::set_ingest_secret(&client, project_id, None).await?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
+
+/// Stops accepting reports sent with the project's access key.
+pub async fn revoke_access_key(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<uuid::Uuid>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let project_id = get_member_project_id(&client, &id, path.into_inner()).await?;
+
+    Project::revoke_access_key(&client, project_id).await?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
+
+#[derive(Deserialize)]
+pub struct RetentionInfo {
+    pub retention_days: Option<i32>,
+}
+
+pub async fn set_retention(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<i32>,
+    retention_info: web::Json<RetentionInfo>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let project_id = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    Project::set_retention_days(&client, project_id, retention_info.retention_days).await?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
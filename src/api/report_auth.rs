@@ -0,0 +1,172 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::HeaderMap;
+use actix_web::{Error, HttpMessage, HttpRequest};
+use deadpool_postgres::{Client, Pool};
+use futures::future::{ok, LocalBoxFuture, Ready};
+
+use crate::db::projects::{hash_ingest_secret, Project};
+use crate::db::reports::ReportInfo;
+use crate::dberror::DataError;
+
+pub const ACCESS_KEY_HEADER: &str = "X-Access-Key";
+pub const INGEST_SECRET_HEADER: &str = "X-Ingest-Secret";
+
+/// The project that the `X-Access-Key` header of a request was authenticated against.
+#[derive(Clone, Copy)]
+pub struct ReportProject {
+    pub project_id: i32,
+    pub access_key: uuid::Uuid,
+}
+
+/// Authenticates ingestion requests that send their access key in the `X-Access-Key` header
+/// (and the project's ingest secret in `X-Ingest-Secret`, if it has one). Requests without
+/// the header are passed on, their access keys are checked per report by `authorized_projects`.
+pub struct CheckReportAuth;
+
+impl<S, B> Transform<S> for CheckReportAuth
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = CheckReportAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CheckReportAuthMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+pub struct CheckReportAuthMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service for CheckReportAuthMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            if let Some(access_key) = header_access_key(req.headers())? {
+                let pool = req
+                    .app_data::<Pool>()
+                    .expect("the database pool is registered with App::data");
+                let client: Client = pool.get().await.map_err(DataError::PoolError)?;
+
+                let secret = req
+                    .headers()
+                    .get(INGEST_SECRET_HEADER)
+                    .and_then(|value| value.to_str().ok());
+                let project_id = authenticate(&client, access_key, secret).await?;
+
+                req.extensions_mut().insert(ReportProject {
+                    project_id,
+                    access_key,
+                });
+            }
+
+            let response = service.borrow_mut().call(req);
+            response.await
+        })
+    }
+}
+
+fn header_access_key(headers: &HeaderMap) -> Result<Option<uuid::Uuid>, DataError> {
+    match headers.get(ACCESS_KEY_HEADER) {
+        None => Ok(None),
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .map(Some)
+            .ok_or(DataError::Unauthorized),
+    }
+}
+
+/// Returns the id of the project of `access_key`, failing with `DataError::Unauthorized` if
+/// the key is unknown or revoked, or if the project's ingest secret doesn't match `secret`.
+pub async fn authenticate(
+    client: &Client,
+    access_key: uuid::Uuid,
+    secret: Option<&str>,
+) -> Result<i32, DataError> {
+    let credentials = Project::get_ingest_credentials(client, access_key)
+        .await?
+        .ok_or(DataError::Unauthorized)?;
+
+    if credentials.revoked {
+        return Err(DataError::Unauthorized);
+    }
+
+    match (credentials.ingest_secret_hash, secret) {
+        (None, _) => Ok(credentials.project_id),
+        (Some(expected_hash), Some(secret)) if secret_matches(&expected_hash, secret) => {
+            Ok(credentials.project_id)
+        }
+        _ => Err(DataError::Unauthorized),
+    }
+}
+
+fn secret_matches(expected_hash: &str, secret: &str) -> bool {
+    let secret_hash = hash_ingest_secret(secret);
+    // `memcmp::eq` panics on different lengths, the length of a hash isn't a secret.
+    expected_hash.len() == secret_hash.len()
+        && openssl::memcmp::eq(expected_hash.as_bytes(), secret_hash.as_bytes())
+}
+
+/// Maps the access keys that `reports` may be saved with to their project ids.
+///
+/// If the request was authenticated by `CheckReportAuth`, reports without an access key are
+/// saved to that project and reports naming a different key are left out. Otherwise each
+/// report's own key is used, which only works for projects without an ingest secret.
+pub async fn authorized_projects(
+    req: &HttpRequest,
+    client: &Client,
+    reports: &mut [ReportInfo],
+) -> Result<HashMap<uuid::Uuid, i32>, DataError> {
+    if let Some(report_project) = req.extensions().get::<ReportProject>().copied() {
+        for report_info in reports.iter_mut() {
+            if report_info.access_key.is_nil() {
+                report_info.access_key = report_project.access_key;
+            }
+        }
+
+        let mut project_ids = HashMap::new();
+        project_ids.insert(report_project.access_key, report_project.project_id);
+        return Ok(project_ids);
+    }
+
+    let mut access_keys = reports
+        .iter()
+        .map(|report_info| report_info.access_key)
+        .collect::<Vec<uuid::Uuid>>();
+    access_keys.sort();
+    access_keys.dedup();
+
+    Project::get_open_project_ids(client, &access_keys).await
+}
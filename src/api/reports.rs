@@ -1,47 +1,134 @@
-use crate::db::reports::{Report, ReportInfo};
-use crate::db::sessions::{GroupedSession, Session, TagGroup, grouped_sessions_to_session_analysis};
+use crate::api::{report_auth, user_auth};
+use crate::db::flows::{get_flow, FlowQuery};
+use crate::db::funnels::{get_funnel, FunnelQuery};
+use crate::db::projects::Project;
+use crate::db::report_validation::ReportProblem;
+use crate::db::reports::{ReportInfo, MAX_BATCH_SIZE};
+use crate::db::retention;
+use crate::db::sessions::{
+    check_tag_groups, grouped_sessions_to_session_analysis, GroupedSession, PageQuery, Session,
+    SessionFilter,
+};
+use crate::db::steps_analytics::tag_group_frequency_at_step;
+use crate::db::tag_groups::{resolve_tag_groups, TagGroupRef};
+use crate::db::timeseries::{get_timeseries, TimeseriesQuery};
 use crate::dberror;
+use actix_identity::Identity;
+use actix_web::error::JsonPayloadError;
 use actix_web::web::Json;
-use actix_web::{web, Error, HttpRequest, HttpResponse, post};
+use actix_web::{web, Error, HttpRequest, HttpResponse};
 use deadpool_postgres::{Client, Pool};
 
-#[post("/reports")]
+/// Answers bodies that can't be read as reports with the same 422 as reports that don't pass
+/// validation.
+pub fn reports_json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        // a full batch of reports doesn't fit in the default 32KB limit
+        .limit(1 << 20)
+        .error_handler(|err, _req| match err {
+            JsonPayloadError::Deserialize(err) => {
+                dberror::DataError::InvalidReport(vec![ReportProblem::new("body", err.to_string())])
+                    .into()
+            }
+            err => err.into(),
+        })
+}
+
 pub async fn save_report(
-    _req: HttpRequest,
+    req: HttpRequest,
     report_info: Json<ReportInfo>,
     db_pool: web::Data<Pool>,
 ) -> Result<HttpResponse, Error> {
-    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+    let mut client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let mut report_info = report_info.into_inner();
+    let project_ids =
+        report_auth::authorized_projects(&req, &client, std::slice::from_mut(&mut report_info))
+            .await?;
+    let project_id = *project_ids
+        .get(&report_info.access_key)
+        .ok_or(dberror::DataError::Unauthorized)?;
 
-    Report::save_report(&client, report_info.into_inner()).await?;
+    ReportInfo::save_report(&mut client, report_info, project_id).await?;
 
     Ok(HttpResponse::Ok().body(""))
 }
 
+pub async fn save_reports(
+    req: HttpRequest,
+    reports: Json<Vec<ReportInfo>>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let mut reports = reports.into_inner();
+    if reports.len() > MAX_BATCH_SIZE {
+        return Err(dberror::DataError::BatchTooLarge.into());
+    }
+
+    let mut client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let project_ids = report_auth::authorized_projects(&req, &client, &mut reports).await?;
+    let summary = ReportInfo::save_reports(&mut client, reports, &project_ids).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&summary)?))
+}
+
 pub async fn get_sessions(
     _req: HttpRequest,
+    id: Identity,
     path: web::Path<i32>,
+    filter: web::Query<SessionFilter>,
+    page: web::Query<PageQuery>,
     db_pool: web::Data<Pool>,
 ) -> Result<HttpResponse, Error> {
     let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
 
-    let sessions = Session::get_sessions(&client, path.into_inner()).await?;
+    let project_id = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    let sessions = Session::get_sessions_page(&client, project_id, &filter, &page).await?;
     let sessions_serialized = serde_json::to_string(&sessions).unwrap();
 
     Ok(HttpResponse::Ok().body(sessions_serialized))
 }
 
+/// Deletes everything the session reported, e.g. when a user asks for their data to be erased.
+pub async fn purge_session(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<(i32, uuid::Uuid)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let mut client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let (project_id, session_id) = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    let deleted_reports = retention::purge_session(&mut client, project_id, session_id).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::json!({ "deleted_reports": deleted_reports }).to_string()))
+}
+
 pub async fn get_grouped_sessions(
     _req: HttpRequest,
+    id: Identity,
     path: web::Path<i32>,
-    tag_groups: web::Json<Vec<TagGroup>>,
+    filter: web::Query<SessionFilter>,
+    tag_groups: web::Json<Vec<TagGroupRef>>,
     db_pool: web::Data<Pool>,
 ) -> Result<HttpResponse, Error> {
     let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
 
-    let tag_groups = tag_groups.into_inner();
+    let project_id = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
 
-    let grouped_sessions = Session::get_sessions(&client, path.into_inner())
+    let tag_groups = resolve_tag_groups(&client, project_id, tag_groups.into_inner()).await?;
+    check_tag_groups(&tag_groups)?;
+
+    let grouped_sessions = Session::get_sessions(&client, project_id, &filter)
         .await?
         .into_iter()
         .map(|session| session.into_grouped_session(&tag_groups))
@@ -52,15 +139,21 @@ pub async fn get_grouped_sessions(
 
 pub async fn get_sessions_analysis(
     _req: HttpRequest,
+    id: Identity,
     path: web::Path<i32>,
-    tag_groups: web::Json<Vec<TagGroup>>,
+    filter: web::Query<SessionFilter>,
+    tag_groups: web::Json<Vec<TagGroupRef>>,
     db_pool: web::Data<Pool>,
 ) -> Result<HttpResponse, Error> {
     let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
 
-    let tag_groups = tag_groups.into_inner();
+    let project_id = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    let tag_groups = resolve_tag_groups(&client, project_id, tag_groups.into_inner()).await?;
+    check_tag_groups(&tag_groups)?;
 
-    let grouped_sessions = Session::get_sessions(&client, path.into_inner())
+    let grouped_sessions = Session::get_sessions(&client, project_id, &filter)
         .await?
         .into_iter()
         .map(|session| session.into_grouped_session(&tag_groups))
@@ -75,14 +168,116 @@ pub async fn get_sessions_analysis(
 
 pub async fn get_percentages(
     _req: HttpRequest,
+    id: Identity,
     path: web::Path<i32>,
-    tag_groups: web::Json<Vec<TagGroup>>,
+    filter: web::Query<SessionFilter>,
+    tag_groups: web::Json<Vec<TagGroupRef>>,
     db_pool: web::Data<Pool>,
 ) -> Result<HttpResponse, Error> {
     let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
-    let tag_groups = tag_groups.into_inner();
 
-    let percentages = Session::get_percentages(&client, path.into_inner(), &tag_groups).await?;
+    let project_id = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    let tag_groups = resolve_tag_groups(&client, project_id, tag_groups.into_inner()).await?;
+
+    let percentages = Session::get_percentages(&client, project_id, &filter, &tag_groups).await?;
 
     Ok(HttpResponse::Ok().body(serde_json::to_string(&percentages).unwrap()))
 }
+
+pub async fn get_funnel_analysis(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<i32>,
+    filter: web::Query<SessionFilter>,
+    mut funnel_query: web::Json<FunnelQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let project_id = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    let tag_groups = std::mem::take(&mut funnel_query.tag_groups);
+    let tag_groups = resolve_tag_groups(&client, project_id, tag_groups).await?;
+
+    let sessions = Session::get_sessions(&client, project_id, &filter).await?;
+    let funnel = get_funnel(&sessions, &tag_groups, &funnel_query)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&funnel)?))
+}
+
+pub async fn get_flow_analysis(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<i32>,
+    filter: web::Query<SessionFilter>,
+    mut flow_query: web::Json<FlowQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let project_id = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    let tag_groups = std::mem::take(&mut flow_query.tag_groups);
+    let tag_groups = resolve_tag_groups(&client, project_id, tag_groups).await?;
+
+    let sessions = Session::get_sessions(&client, project_id, &filter).await?;
+    let flow = get_flow(&sessions, &tag_groups, &flow_query)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&flow)?))
+}
+
+pub async fn get_step_frequencies(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<(i32, usize)>,
+    filter: web::Query<SessionFilter>,
+    tag_groups: web::Json<Vec<TagGroupRef>>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let (project_id, step_number) = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    let tag_groups = resolve_tag_groups(&client, project_id, tag_groups.into_inner()).await?;
+    check_tag_groups(&tag_groups)?;
+
+    let grouped_sessions = Session::get_sessions(&client, project_id, &filter)
+        .await?
+        .into_iter()
+        .map(|session| session.into_grouped_session(&tag_groups))
+        .collect::<Vec<GroupedSession>>();
+
+    let frequencies = tag_group_frequency_at_step(&grouped_sessions, step_number);
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&frequencies)?))
+}
+
+pub async fn get_project_timeseries(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<i32>,
+    query: web::Query<TimeseriesQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let project_id = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    let timeseries = get_timeseries(&client, project_id, &query).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&timeseries)?))
+}
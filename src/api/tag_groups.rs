@@ -0,0 +1,80 @@
+use crate::api::user_auth;
+use crate::db::projects::Project;
+use crate::db::tag_groups::{SavedTagGroup, TagGroupInfo};
+use crate::dberror;
+use actix_identity::Identity;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use deadpool_postgres::{Client, Pool};
+
+pub async fn get_tag_groups(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<i32>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let project_id = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    let tag_groups = SavedTagGroup::get_tag_groups(&client, project_id).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&tag_groups)?))
+}
+
+pub async fn save_tag_group(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<i32>,
+    tag_group_info: web::Json<TagGroupInfo>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let project_id = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    let tag_group = SavedTagGroup::save_tag_group(&client, project_id, &tag_group_info).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&tag_group)?))
+}
+
+pub async fn update_tag_group(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<(i32, i32)>,
+    tag_group_info: web::Json<TagGroupInfo>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let (project_id, tag_group_id) = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    let tag_group =
+        SavedTagGroup::update_tag_group(&client, project_id, tag_group_id, &tag_group_info).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&tag_group)?))
+}
+
+pub async fn delete_tag_group(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<(i32, i32)>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let (project_id, tag_group_id) = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    SavedTagGroup::delete_tag_group(&client, project_id, tag_group_id).await?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
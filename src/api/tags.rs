@@ -0,0 +1,43 @@
+use crate::api::user_auth;
+use crate::db::projects::Project;
+use crate::db::tags::{self, TagStatsQuery, TagUpdate};
+use crate::dberror;
+use actix_identity::Identity;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use deadpool_postgres::{Client, Pool};
+
+pub async fn get_tag_stats(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<i32>,
+    query: web::Query<TagStatsQuery>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let project_id = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    let tag_stats = tags::get_tag_stats(&client, project_id, &query).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(serde_json::to_string(&tag_stats)?))
+}
+
+pub async fn update_tags(
+    _req: HttpRequest,
+    id: Identity,
+    path: web::Path<i32>,
+    tag_update: web::Json<TagUpdate>,
+    db_pool: web::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let project_id = path.into_inner();
+    Project::check_membership(&client, user_auth::user_id(&id)?, project_id).await?;
+
+    tags::update_tags(&client, project_id, &tag_update).await?;
+
+    Ok(HttpResponse::Ok().body(""))
+}
@@ -1,11 +1,20 @@
 use std::task::{Context, Poll};
 
-use actix_identity::RequestIdentity;
+use actix_identity::{Identity, RequestIdentity};
 use actix_service::{Service, Transform};
 use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::{http, Error, HttpResponse};
 use futures::future::{ok, Either, Ready};
 
+use crate::dberror::DataError;
+
+/// Returns the id of the logged-in user, as remembered by `users::login`.
+pub fn user_id(id: &Identity) -> Result<i32, DataError> {
+    id.identity()
+        .and_then(|user_id| user_id.parse().ok())
+        .ok_or(DataError::Unauthorized)
+}
+
 pub struct CheckLogin;
 
 impl<S, B> Transform<S> for CheckLogin
@@ -3,15 +3,48 @@ use actix_web::{web, Error, HttpRequest, HttpResponse};
 use deadpool_postgres::{Client, Pool};
 use serde::{Deserialize, Serialize};
 
+use crate::api::user_auth;
 use crate::db::users::User;
 use crate::dberror;
 
+const MIN_PASSWORD_LEN: usize = 8;
+// the lengths of `main.users.username` and `main.users.email`
+const MAX_USERNAME_LEN: usize = 50;
+const MAX_EMAIL_LEN: usize = 254;
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct LoginInfo {
     pub email: String,
     pub password: String,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RegisterInfo {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+}
+
+impl RegisterInfo {
+    fn validate(&self) -> Result<(), dberror::DataError> {
+        let problem =
+            if self.username.is_empty() || self.username.chars().count() > MAX_USERNAME_LEN {
+                format!("username must have 1 to {} characters", MAX_USERNAME_LEN)
+            } else if !self.email.contains('@') || self.email.chars().count() > MAX_EMAIL_LEN {
+                "email is not valid".to_string()
+            } else if self.password.chars().count() < MIN_PASSWORD_LEN {
+                format!(
+                    "password must have at least {} characters",
+                    MIN_PASSWORD_LEN
+                )
+            } else {
+                return Ok(());
+            };
+
+        Err(dberror::DataError::InvalidUser(problem))
+    }
+}
+
 pub async fn login(
     login_info: web::Json<LoginInfo>,
     id: Identity,
@@ -28,16 +61,34 @@ pub async fn login(
     Ok(HttpResponse::Ok().body(""))
 }
 
-pub async fn greet(
-    _req: HttpRequest,
-    path: web::Path<String>,
+/// Creates the account and logs the new user in.
+pub async fn register(
+    register_info: web::Json<RegisterInfo>,
+    id: Identity,
     db_pool: web::Data<Pool>,
 ) -> Result<User, Error> {
-    let username = path.into_inner();
+    let register_info = register_info.into_inner();
+    register_info.validate()?;
+
+    let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
+
+    let user = User::register(&client, &register_info).await?;
+
+    id.remember(user.user_id.to_string());
+
+    Ok(user)
+}
+
+pub async fn logout(id: Identity) -> HttpResponse {
+    id.forget();
+
+    HttpResponse::Ok().body("")
+}
 
+pub async fn me(_req: HttpRequest, id: Identity, db_pool: web::Data<Pool>) -> Result<User, Error> {
     let client: Client = db_pool.get().await.map_err(dberror::DataError::PoolError)?;
 
-    let user = User::get_user_by_name(&client, username).await?;
+    let user = User::get_user_by_id(&client, user_auth::user_id(&id)?).await?;
 
     Ok(user)
 }
@@ -1,16 +1,130 @@
+use actix_web::http::Uri;
 use config::ConfigError;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use postgres_openssl::MakeTlsConnector;
+use rand::Rng;
 use serde::Deserialize;
 
+/// `CookieIdentityPolicy` needs a key of at least 32 bytes.
+const MIN_COOKIE_KEY_LEN: usize = 32;
+
 #[derive(Deserialize)]
 pub struct Config {
+    #[serde(default = "default_server_addr")]
     pub server_addr: String,
     pub pg: deadpool_postgres::Config,
+    /// The private key of the identity cookies, `cookie_key_file` is used if it isn't set.
+    /// A random key is generated if neither is set, logging everyone out on restarts.
+    #[serde(default)]
+    pub cookie_key: Option<String>,
+    #[serde(default)]
+    pub cookie_key_file: Option<String>,
+    #[serde(default = "default_true")]
+    pub secure_cookies: bool,
+    /// A CA bundle to verify the database's certificate with, instead of the system's.
+    #[serde(default)]
+    pub pg_ca_file: Option<String>,
+    #[serde(default = "default_true")]
+    pub pg_verify_tls: bool,
+    /// Comma separated origins allowed to make credentialed cross-origin requests.
+    /// Cross-origin requests are refused if it isn't set.
+    #[serde(default)]
+    pub allowed_origins: Option<String>,
+    /// How often reports past their project's retention window are deleted.
+    #[serde(default = "default_retention_interval_secs")]
+    pub retention_interval_secs: u64,
+    /// Where to archive the reports before deleting them, they're only deleted if it isn't set.
+    #[serde(default)]
+    pub archive_dir: Option<String>,
+}
+
+fn default_server_addr() -> String {
+    "127.0.0.1:9000".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_retention_interval_secs() -> u64 {
+    60 * 60
 }
 
 impl Config {
     pub fn from_env() -> Result<Self, ConfigError> {
         let mut cfg = ::config::Config::new();
         cfg.merge(::config::Environment::new())?;
-        cfg.try_into()
+        let config: Config = cfg.try_into()?;
+
+        if config.retention_interval_secs == 0 {
+            return Err(ConfigError::Message(
+                "retention_interval_secs must be at least 1".to_string(),
+            ));
+        }
+        Ok(config)
+    }
+
+    pub fn cookie_key(&self) -> Result<Vec<u8>, ConfigError> {
+        let key = match (&self.cookie_key, &self.cookie_key_file) {
+            (Some(key), _) => key.as_bytes().to_vec(),
+            (None, Some(path)) => std::fs::read(path).map_err(|err| {
+                ConfigError::Message(format!("can't read cookie_key_file {}: {}", path, err))
+            })?,
+            (None, None) => {
+                eprintln!("cookie_key is not set, using a random key");
+                return Ok(rand::thread_rng().gen::<[u8; 32]>().to_vec());
+            }
+        };
+
+        if key.len() < MIN_COOKIE_KEY_LEN {
+            return Err(ConfigError::Message(format!(
+                "the cookie key must be at least {} bytes long",
+                MIN_COOKIE_KEY_LEN
+            )));
+        }
+        Ok(key)
     }
+
+    pub fn tls_connector(&self) -> Result<MakeTlsConnector, ConfigError> {
+        let mut builder = SslConnector::builder(SslMethod::tls()).map_err(tls_error)?;
+
+        if let Some(path) = &self.pg_ca_file {
+            builder.set_ca_file(path).map_err(|err| {
+                ConfigError::Message(format!("can't load pg_ca_file {}: {}", path, err))
+            })?;
+        }
+
+        if self.pg_verify_tls {
+            builder.set_verify(SslVerifyMode::PEER);
+        } else {
+            eprintln!("pg_verify_tls is off, the database's certificate won't be verified");
+            builder.set_verify(SslVerifyMode::NONE);
+        }
+
+        Ok(MakeTlsConnector::new(builder.build()))
+    }
+
+    pub fn allowed_origins(&self) -> Result<Vec<String>, ConfigError> {
+        let origins = match &self.allowed_origins {
+            Some(origins) => origins,
+            None => return Ok(vec![]),
+        };
+
+        origins
+            .split(',')
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .map(|origin| match origin.parse::<Uri>() {
+                Ok(uri) if uri.scheme().is_some() && uri.host().is_some() => Ok(origin.to_string()),
+                _ => Err(ConfigError::Message(format!(
+                    "allowed origin {} is not a valid origin",
+                    origin
+                ))),
+            })
+            .collect()
+    }
+}
+
+fn tls_error(err: openssl::error::ErrorStack) -> ConfigError {
+    ConfigError::Message(format!("can't set up TLS: {}", err))
 }
@@ -0,0 +1,275 @@
+use crate::db::sessions::{check_tag_groups, Session, TagGroup};
+use crate::db::tag_groups::TagGroupRef;
+use crate::dberror::DataError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const DEFAULT_DEPTH: usize = 3;
+const MAX_DEPTH: usize = 10;
+const DEFAULT_TOP: usize = 5;
+
+#[derive(Deserialize)]
+pub struct FlowQuery {
+    /// The groups reports are classified into, reports matching none of them are skipped.
+    pub tag_groups: Vec<TagGroupRef>,
+    /// The id of the tag group the flow starts from.
+    pub start: i32,
+    /// How many groups to follow before and after the start.
+    pub depth: Option<usize>,
+    /// How many of the most common groups to keep at each node.
+    pub top: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct FlowNode {
+    pub tag_group_id: i32,
+    /// The number of sessions that went through this node.
+    pub count: usize,
+    /// The average millisecs between this group and its parent.
+    pub average_duration: i64,
+    pub children: Vec<FlowNode>,
+}
+
+#[derive(Serialize)]
+pub struct Flow {
+    pub start: i32,
+    /// The number of sessions that went through the start group.
+    pub sessions: usize,
+    /// The groups that came after the start group, most common first.
+    pub following: Vec<FlowNode>,
+    /// The groups that came before the start group, most common first.
+    pub preceding: Vec<FlowNode>,
+}
+
+/// Follows every session from the first time it reached the start group, in both directions.
+/// `tag_groups` are the groups of `query`, resolved, the start group must be one of them.
+pub fn get_flow(
+    sessions: &[Session],
+    tag_groups: &[TagGroup],
+    query: &FlowQuery,
+) -> Result<Flow, DataError> {
+    check_tag_groups(tag_groups)?;
+    if !tag_groups
+        .iter()
+        .any(|tag_group| tag_group.id == query.start)
+    {
+        return Err(DataError::InvalidTagGroups(format!(
+            "the start tag group {} is not one of the tag groups",
+            query.start
+        )));
+    }
+
+    let depth = query.depth.unwrap_or(DEFAULT_DEPTH).min(MAX_DEPTH);
+    let top = query.top.unwrap_or(DEFAULT_TOP);
+
+    let mut following = Vec::<Vec<(i32, i64)>>::new();
+    let mut preceding = Vec::<Vec<(i32, i64)>>::new();
+
+    for session in sessions {
+        let sequence = group_sequence(session, tag_groups);
+        let start = match sequence.iter().position(|(id, _)| *id == query.start) {
+            Some(start) => start,
+            None => continue,
+        };
+
+        following.push(
+            sequence[start..]
+                .windows(2)
+                .take(depth)
+                .map(|pair| (pair[1].0, pair[1].1 - pair[0].1))
+                .collect(),
+        );
+        preceding.push(
+            sequence[..=start]
+                .windows(2)
+                .rev()
+                .take(depth)
+                .map(|pair| (pair[0].0, pair[1].1 - pair[0].1))
+                .collect(),
+        );
+    }
+
+    Ok(Flow {
+        start: query.start,
+        sessions: following.len(),
+        following: build_nodes(
+            &following.iter().map(Vec::as_slice).collect::<Vec<_>>(),
+            top,
+        ),
+        preceding: build_nodes(
+            &preceding.iter().map(Vec::as_slice).collect::<Vec<_>>(),
+            top,
+        ),
+    })
+}
+
+/// Maps the session's reports to the ids of their tag groups, with the time each group was
+/// reached. Consecutive reports of the same group are merged.
+fn group_sequence(session: &Session, tag_groups: &[TagGroup]) -> Vec<(i32, i64)> {
+    let mut sequence = Vec::<(i32, i64)>::new();
+
+    for report_info in &session.reports {
+        let tag_group_id = match tag_groups
+            .iter()
+            .find(|tag_group| tag_group.matches(&report_info.tags))
+        {
+            Some(tag_group) => tag_group.id,
+            None => continue,
+        };
+
+        if sequence
+            .last()
+            .is_none_or(|(last_id, _)| *last_id != tag_group_id)
+        {
+            sequence.push((tag_group_id, report_info.time_ms));
+        }
+    }
+
+    sequence
+}
+
+/// Builds the tree of paths, each path being the ids of the groups it goes through with the
+/// millisecs from the previous group.
+fn build_nodes(paths: &[&[(i32, i64)]], top: usize) -> Vec<FlowNode> {
+    let mut paths_by_group = HashMap::<i32, Vec<&[(i32, i64)]>>::new();
+    for &path in paths {
+        if let Some((tag_group_id, _)) = path.first() {
+            paths_by_group.entry(*tag_group_id).or_default().push(path);
+        }
+    }
+
+    let mut paths_by_group = paths_by_group.into_iter().collect::<Vec<_>>();
+    paths_by_group.sort_by(|(l_id, l_paths), (r_id, r_paths)| {
+        r_paths.len().cmp(&l_paths.len()).then(l_id.cmp(r_id))
+    });
+    paths_by_group.truncate(top);
+
+    paths_by_group
+        .into_iter()
+        .map(|(tag_group_id, paths)| {
+            let duration_sum = paths.iter().map(|path| path[0].1).sum::<i64>();
+            let tails = paths.iter().map(|path| &path[1..]).collect::<Vec<_>>();
+
+            FlowNode {
+                tag_group_id,
+                count: paths.len(),
+                average_duration: duration_sum / paths.len() as i64,
+                children: build_nodes(&tails, top),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::reports::ReportInfo;
+
+    fn tag_group(id: i32, tags_names: &[&str]) -> TagGroup {
+        let tags_names = tags_names.iter().map(|name| name.to_string()).collect();
+        TagGroup::new(id, tags_names, None).unwrap()
+    }
+
+    fn session(reports: &[(i64, &str)]) -> Session {
+        let session_id = uuid::Uuid::new_v4();
+        Session {
+            session_id,
+            reports: reports
+                .iter()
+                .map(|&(time_ms, tag)| ReportInfo {
+                    access_key: uuid::Uuid::nil(),
+                    session_id,
+                    time_ms,
+                    tags: vec![tag.to_string()],
+                    properties: serde_json::json!({}),
+                    sent_at: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn query(start: i32, depth: Option<usize>) -> FlowQuery {
+        FlowQuery {
+            tag_groups: vec![],
+            start,
+            depth,
+            top: None,
+        }
+    }
+
+    fn tag_groups() -> Vec<TagGroup> {
+        vec![
+            tag_group(1, &["home"]),
+            tag_group(2, &["cart"]),
+            tag_group(3, &["paid"]),
+            tag_group(4, &["help"]),
+        ]
+    }
+
+    fn sessions() -> Vec<Session> {
+        vec![
+            session(&[(0, "home"), (10, "cart"), (30, "paid")]),
+            session(&[(0, "home"), (5, "help"), (15, "cart"), (40, "help")]),
+            session(&[(0, "paid"), (100, "cart"), (110, "paid"), (200, "cart")]),
+            session(&[(0, "home"), (10, "unknown")]),
+        ]
+    }
+
+    /// `(tag group id, count, average duration, children)` of each node.
+    fn nodes(nodes: &[FlowNode]) -> Vec<(i32, usize, i64, usize)> {
+        nodes
+            .iter()
+            .map(|node| {
+                (
+                    node.tag_group_id,
+                    node.count,
+                    node.average_duration,
+                    node.children.len(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn following_paths_start_at_the_first_visit() {
+        let flow = get_flow(&sessions(), &tag_groups(), &query(2, None)).unwrap();
+
+        assert_eq!(flow.sessions, 3);
+        assert_eq!(nodes(&flow.following), vec![(3, 2, 15, 1), (4, 1, 25, 0)]);
+        assert_eq!(nodes(&flow.following[0].children), vec![(2, 1, 90, 0)]);
+    }
+
+    #[test]
+    fn preceding_paths_go_backwards() {
+        let flow = get_flow(&sessions(), &tag_groups(), &query(2, None)).unwrap();
+
+        assert_eq!(
+            nodes(&flow.preceding),
+            vec![(1, 1, 10, 0), (3, 1, 100, 0), (4, 1, 10, 1)]
+        );
+        assert_eq!(nodes(&flow.preceding[2].children), vec![(1, 1, 5, 0)]);
+    }
+
+    #[test]
+    fn paths_stop_at_the_depth() {
+        let flow = get_flow(&sessions(), &tag_groups(), &query(2, Some(1))).unwrap();
+
+        assert!(flow.following.iter().all(|node| node.children.is_empty()));
+        assert!(flow.preceding.iter().all(|node| node.children.is_empty()));
+    }
+
+    #[test]
+    fn the_start_must_be_one_of_the_tag_groups() {
+        match get_flow(&sessions(), &tag_groups(), &query(5, None)) {
+            Err(DataError::InvalidTagGroups(_)) => {}
+            _ => panic!("an unknown start should be rejected"),
+        }
+    }
+
+    #[test]
+    fn duplicate_tag_group_ids_are_rejected() {
+        let tag_groups = vec![tag_group(1, &["home"]), tag_group(1, &["cart"])];
+
+        assert!(get_flow(&sessions(), &tag_groups, &query(1, None)).is_err());
+    }
+}
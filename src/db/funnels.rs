@@ -0,0 +1,266 @@
+use crate::db::percentage::Percentage;
+use crate::db::sessions::{Session, TagGroup};
+use crate::db::tag_groups::TagGroupRef;
+use crate::dberror::DataError;
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct FunnelQuery {
+    /// The steps of the funnel, in order.
+    pub tag_groups: Vec<TagGroupRef>,
+    /// The most millisecs allowed from the first to the last step reached by a session.
+    pub max_window: Option<i64>,
+}
+
+impl FunnelQuery {
+    fn max_window(&self) -> Result<Option<i64>, DataError> {
+        match self.max_window {
+            Some(max_window) if max_window < 0 => Err(DataError::InvalidFilter(
+                "max_window can't be negative".to_string(),
+            )),
+            max_window => Ok(max_window),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct FunnelStep {
+    pub step_number: usize,
+    pub tag_group: TagGroup,
+    /// The number of sessions that reached this step after all the previous ones.
+    pub sessions: usize,
+    /// The share of the sessions that reached the previous step (all sessions for the first
+    /// step) that also reached this one.
+    pub conversion_rate: Percentage,
+    pub drop_off: usize,
+    /// In millisecs, `None` for the first step and for steps that no session reached.
+    pub median_time_from_previous: Option<i64>,
+}
+
+/// `tag_groups` are the steps of `query`, resolved.
+pub fn get_funnel(
+    sessions: &[Session],
+    tag_groups: &[TagGroup],
+    query: &FunnelQuery,
+) -> Result<Vec<FunnelStep>, DataError> {
+    let max_window = query.max_window()?;
+    let step_count = tag_groups.len();
+    let mut reached = vec![0; step_count];
+    let mut times_from_previous = vec![Vec::<i64>::new(); step_count];
+
+    for session in sessions {
+        let step_times = get_step_times(session, tag_groups, max_window);
+
+        for (step_number, time_ms) in step_times.iter().enumerate() {
+            reached[step_number] += 1;
+            if step_number > 0 {
+                times_from_previous[step_number].push(time_ms - step_times[step_number - 1]);
+            }
+        }
+    }
+
+    Ok(tag_groups
+        .iter()
+        .zip(times_from_previous)
+        .enumerate()
+        .map(|(step_number, (tag_group, mut times))| {
+            let previous = if step_number == 0 {
+                sessions.len()
+            } else {
+                reached[step_number - 1]
+            };
+            let conversion_rate = (reached[step_number] * 100)
+                .checked_div(previous)
+                .unwrap_or(0);
+
+            FunnelStep {
+                step_number,
+                tag_group: tag_group.clone(),
+                sessions: reached[step_number],
+                conversion_rate: (conversion_rate as u32).into(),
+                drop_off: previous - reached[step_number],
+                median_time_from_previous: median(&mut times),
+            }
+        })
+        .collect())
+}
+
+/// Returns when the session reached each step of the funnel, for the run that gets the
+/// furthest. A run starts at a report of the first step and takes the earliest matching
+/// report for each next step.
+fn get_step_times(session: &Session, tag_groups: &[TagGroup], max_window: Option<i64>) -> Vec<i64> {
+    let mut best_run = vec![];
+
+    let first_step = match tag_groups.first() {
+        Some(tag_group) => tag_group,
+        None => return best_run,
+    };
+
+    for (start, report_info) in session.reports.iter().enumerate() {
+        if !first_step.matches(&report_info.tags) {
+            continue;
+        }
+
+        let mut run = vec![report_info.time_ms];
+        for later_report in &session.reports[start + 1..] {
+            if run.len() == tag_groups.len() {
+                break;
+            }
+            if max_window
+                .is_some_and(|max_window| later_report.time_ms - report_info.time_ms > max_window)
+            {
+                break;
+            }
+            if tag_groups[run.len()].matches(&later_report.tags) {
+                run.push(later_report.time_ms);
+            }
+        }
+
+        if run.len() > best_run.len() {
+            best_run = run;
+            if best_run.len() == tag_groups.len() {
+                break;
+            }
+        }
+    }
+
+    best_run
+}
+
+fn median(values: &mut [i64]) -> Option<i64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    values.sort();
+    Some(values[values.len() / 2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::reports::ReportInfo;
+
+    fn tag_group(id: i32, tags_names: &[&str]) -> TagGroup {
+        let tags_names = tags_names.iter().map(|name| name.to_string()).collect();
+        TagGroup::new(id, tags_names, None).unwrap()
+    }
+
+    fn session(reports: &[(i64, &str)]) -> Session {
+        let session_id = uuid::Uuid::new_v4();
+        Session {
+            session_id,
+            reports: reports
+                .iter()
+                .map(|&(time_ms, tag)| ReportInfo {
+                    access_key: uuid::Uuid::nil(),
+                    session_id,
+                    time_ms,
+                    tags: vec![tag.to_string()],
+                    properties: serde_json::json!({}),
+                    sent_at: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn query(max_window: Option<i64>) -> FunnelQuery {
+        FunnelQuery {
+            tag_groups: vec![],
+            max_window,
+        }
+    }
+
+    fn reached(funnel: &[FunnelStep]) -> Vec<usize> {
+        funnel.iter().map(|step| step.sessions).collect()
+    }
+
+    fn steps() -> Vec<TagGroup> {
+        vec![
+            tag_group(1, &["home"]),
+            tag_group(2, &["cart"]),
+            tag_group(3, &["paid"]),
+        ]
+    }
+
+    #[test]
+    fn steps_must_be_reached_in_order() {
+        let sessions = vec![
+            session(&[(0, "home"), (10, "cart"), (20, "paid")]),
+            session(&[(0, "cart"), (10, "home"), (20, "paid")]),
+            session(&[(0, "paid"), (10, "cart")]),
+        ];
+
+        let funnel = get_funnel(&sessions, &steps(), &query(None)).unwrap();
+
+        assert_eq!(reached(&funnel), vec![2, 1, 1]);
+        assert_eq!(funnel[0].drop_off, 1);
+        assert_eq!(funnel[1].drop_off, 1);
+        assert_eq!(funnel[2].drop_off, 0);
+        assert_eq!(funnel[1].conversion_rate, Percentage::from(50u32));
+    }
+
+    #[test]
+    fn the_run_that_gets_the_furthest_counts() {
+        let sessions = vec![session(&[
+            (0, "home"),
+            (10, "paid"),
+            (20, "home"),
+            (30, "cart"),
+            (40, "paid"),
+        ])];
+
+        let funnel = get_funnel(&sessions, &steps(), &query(Some(25))).unwrap();
+
+        assert_eq!(reached(&funnel), vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn steps_past_the_max_window_are_not_reached() {
+        let sessions = vec![
+            session(&[(0, "home"), (50, "cart"), (100, "paid")]),
+            session(&[(0, "home"), (50, "cart"), (101, "paid")]),
+        ];
+
+        let funnel = get_funnel(&sessions, &steps(), &query(Some(100))).unwrap();
+
+        assert_eq!(reached(&funnel), vec![2, 2, 1]);
+    }
+
+    #[test]
+    fn negative_max_window_is_rejected() {
+        let sessions = vec![session(&[(0, "home")])];
+
+        match get_funnel(&sessions, &steps(), &query(Some(-1))) {
+            Err(DataError::InvalidFilter(_)) => {}
+            _ => panic!("a negative max_window should be rejected"),
+        }
+    }
+
+    #[test]
+    fn medians_are_of_the_sessions_reaching_the_step() {
+        let sessions = vec![
+            session(&[(0, "home"), (10, "cart"), (15, "paid")]),
+            session(&[(0, "home"), (30, "cart")]),
+            session(&[(0, "home"), (20, "cart"), (60, "paid")]),
+            session(&[(0, "home")]),
+        ];
+
+        let funnel = get_funnel(&sessions, &steps(), &query(None)).unwrap();
+
+        assert_eq!(funnel[0].median_time_from_previous, None);
+        assert_eq!(funnel[1].median_time_from_previous, Some(20));
+        assert_eq!(funnel[2].median_time_from_previous, Some(40));
+    }
+
+    #[test]
+    fn unreached_steps_have_no_median() {
+        let sessions = vec![session(&[(0, "home")])];
+
+        let funnel = get_funnel(&sessions, &steps(), &query(None)).unwrap();
+
+        assert_eq!(reached(&funnel), vec![1, 0, 0]);
+        assert_eq!(funnel[1].median_time_from_previous, None);
+        assert_eq!(funnel[1].conversion_rate, Percentage::from(0u32));
+    }
+}
@@ -0,0 +1,152 @@
+use crate::dberror::DataError;
+use deadpool_postgres::Client;
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    sql: &'static str,
+}
+
+/// Every migration in the order they are applied, a new one gets the next version and its
+/// file is named `<version>_<name>.sql`.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../../sql/migrations/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "ingest_credentials",
+        sql: include_str!("../../sql/migrations/0002_ingest_credentials.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "password_hashes",
+        sql: include_str!("../../sql/migrations/0003_password_hashes.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "tag_groups",
+        sql: include_str!("../../sql/migrations/0004_tag_groups.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "tag_rules",
+        sql: include_str!("../../sql/migrations/0005_tag_rules.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "report_properties",
+        sql: include_str!("../../sql/migrations/0006_report_properties.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "received_at",
+        sql: include_str!("../../sql/migrations/0007_received_at.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "retention",
+        sql: include_str!("../../sql/migrations/0008_retention.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "partitioned_reports",
+        sql: include_str!("../../sql/migrations/0009_partitioned_reports.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "daily_rollups",
+        sql: include_str!("../../sql/migrations/0010_daily_rollups.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "project_tags",
+        sql: include_str!("../../sql/migrations/0011_project_tags.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "project_clock_skews",
+        sql: include_str!("../../sql/migrations/0012_project_clock_skews.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "report_tags_tag_id",
+        sql: include_str!("../../sql/migrations/0013_report_tags_tag_id.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "ingest_secret_hashes",
+        sql: include_str!("../../sql/migrations/0014_ingest_secret_hashes.sql"),
+    },
+];
+
+/// The schema version this binary expects.
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Returns the version of the last applied migration, 0 if none were applied.
+pub async fn current_version(client: &Client) -> Result<i32, DataError> {
+    let stmt_str = include_str!("../../sql/schema_migrations_exists.sql");
+    let stmt = client.prepare(stmt_str).await?;
+
+    let exists: bool = client.query_one(&stmt, &[]).await?.get("exists");
+    if !exists {
+        return Ok(0);
+    }
+
+    let stmt_str = include_str!("../../sql/get_schema_version.sql");
+    let stmt = client.prepare(stmt_str).await?;
+
+    Ok(client.query_one(&stmt, &[]).await?.get("version"))
+}
+
+/// Fails with `DataError::OutdatedSchema` if migrations that this binary needs weren't applied.
+pub async fn check_version(client: &Client) -> Result<(), DataError> {
+    let version = current_version(client).await?;
+    if version < latest_version() {
+        Err(DataError::OutdatedSchema(version))
+    } else {
+        Ok(())
+    }
+}
+
+/// Applies the pending migrations in one transaction and returns them.
+pub async fn migrate(client: &mut Client) -> Result<Vec<&'static Migration>, DataError> {
+    client
+        .batch_execute(include_str!("../../sql/create_schema_migrations.sql"))
+        .await?;
+
+    let transaction = client.transaction().await?;
+    // keeps servers started at the same time from applying the same migrations
+    transaction
+        .batch_execute(include_str!("../../sql/lock_schema_migrations.sql"))
+        .await?;
+
+    let stmt = transaction
+        .prepare(include_str!("../../sql/get_schema_version.sql"))
+        .await?;
+    let version: i32 = transaction.query_one(&stmt, &[]).await?.get("version");
+
+    let insert_migration = transaction
+        .prepare(include_str!("../../sql/insert_schema_migration.sql"))
+        .await?;
+
+    let pending = MIGRATIONS
+        .iter()
+        .filter(|migration| migration.version > version)
+        .collect::<Vec<&Migration>>();
+
+    for migration in &pending {
+        transaction.batch_execute(migration.sql).await?;
+        transaction
+            .execute(&insert_migration, &[&migration.version, &migration.name])
+            .await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(pending)
+}
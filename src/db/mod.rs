@@ -1,5 +1,35 @@
+pub mod flows;
+pub mod funnels;
+pub mod migrations;
+pub mod partitions;
 pub mod percentage;
 pub mod sessions;
+pub mod steps_analytics;
+pub mod tag_groups;
+pub mod tag_rules;
+pub mod tags;
+pub mod timeseries;
 pub mod projects;
+pub mod report_validation;
 pub mod reports;
+pub mod retention;
+pub mod rollups;
 pub mod users;
+
+use crate::dberror::DataError;
+use actix_web::error::BlockingError;
+use actix_web::web;
+use std::io;
+
+/// Runs blocking work, on files or hashing passwords, on the thread pool rather than on the
+/// server's threads.
+pub(crate) async fn blocking<F, T>(f: F) -> Result<T, DataError>
+where
+    F: FnOnce() -> Result<T, DataError> + Send + 'static,
+    T: Send + 'static,
+{
+    web::block(f).await.map_err(|err| match err {
+        BlockingError::Error(err) => err,
+        BlockingError::Canceled => DataError::IoError(io::Error::other("the thread pool is gone")),
+    })
+}
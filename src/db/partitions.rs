@@ -0,0 +1,17 @@
+use crate::dberror::DataError;
+use deadpool_postgres::Client;
+
+/// Reports are partitioned by month, the partitions are created this many months ahead so
+/// that new reports never land in the default partition.
+const MONTHS_AHEAD: i32 = 2;
+
+/// Creates the partitions of `main.reports` for this month and the next `MONTHS_AHEAD` ones
+/// that don't exist yet.
+pub async fn create_partitions(client: &Client) -> Result<(), DataError> {
+    let stmt_str = include_str!("../../sql/create_reports_partitions.sql");
+    let stmt = client.prepare(stmt_str).await?;
+
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    client.execute(&stmt, &[&now_ms, &MONTHS_AHEAD]).await?;
+    Ok(())
+}
@@ -2,18 +2,31 @@ use crate::dberror::DataError;
 use actix_web::{Error, HttpRequest, HttpResponse, Responder};
 use deadpool_postgres::Client;
 use futures::future::{ready, Ready};
-use postgres_types::FromSql;
 use serde::{Deserialize, Serialize};
 use tokio_pg_mapper::FromTokioPostgresRow;
 use tokio_pg_mapper_derive::PostgresMapper;
 use uuid::Uuid;
-use std::time::Duration;
+use std::collections::HashMap;
 
 #[derive(Deserialize, Serialize, PostgresMapper)]
 #[pg_mapper(table = "users")]
 pub struct Project {
+    /// Identifies the project in the routes of the dashboard, `/projects/{project_id}/...`.
+    project_id: i32,
     name: String,
     access_key: Uuid,
+    /// Reports received more than this many days ago are deleted, `None` keeps them forever.
+    retention_days: Option<i32>,
+}
+
+/// What is needed to authenticate reports sent with a project's access key.
+#[derive(PostgresMapper)]
+#[pg_mapper(table = "projects")]
+pub struct IngestCredentials {
+    pub project_id: i32,
+    /// See `hash_ingest_secret`.
+    pub ingest_secret_hash: Option<String>,
+    pub revoked: bool,
 }
 
 impl Responder for Project {
@@ -53,8 +66,111 @@ impl Project {
         let stmt_str = include_str!("../../sql/project_id_from_access_key.sql");
         let stmt = client.prepare(stmt_str).await?;
 
-        let row = client.query_one(&stmt, &[&access_key]).await?;
-        Ok(row.get("project_id"))
+        client
+            .query(&stmt, &[&access_key])
+            .await?
+            .iter()
+            .map(|row| row.get("project_id"))
+            .collect::<Vec<i32>>()
+            .pop()
+            .ok_or(DataError::NotFound)
+    }
+
+    pub async fn get_ingest_credentials(
+        client: &Client,
+        access_key: uuid::Uuid,
+    ) -> Result<Option<IngestCredentials>, DataError> {
+        let stmt_str = include_str!("../../sql/get_ingest_credentials.sql");
+        let stmt = client.prepare(stmt_str).await?;
+
+        Ok(client
+            .query(&stmt, &[&access_key])
+            .await?
+            .iter()
+            .map(|row| IngestCredentials::from_row_ref(row).unwrap())
+            .collect::<Vec<IngestCredentials>>()
+            .pop())
+    }
+
+    /// Maps the access keys that can be used without an ingest secret to their project ids.
+    /// Unknown and revoked keys are left out.
+    pub async fn get_open_project_ids(
+        client: &Client,
+        access_keys: &[uuid::Uuid],
+    ) -> Result<HashMap<uuid::Uuid, i32>, DataError> {
+        let stmt_str = include_str!("../../sql/open_projects_from_access_keys.sql");
+        let stmt = client.prepare(stmt_str).await?;
+
+        Ok(client
+            .query(&stmt, &[&access_keys])
+            .await?
+            .iter()
+            .map(|row| (row.get("access_key"), row.get("project_id")))
+            .collect())
+    }
+
+    /// Saves the hash of the secret, which is never saved itself.
+    pub async fn set_ingest_secret(
+        client: &Client,
+        project_id: i32,
+        ingest_secret: Option<&str>,
+    ) -> Result<(), DataError> {
+        let stmt_str = include_str!("../../sql/set_ingest_secret.sql");
+        let stmt = client.prepare(stmt_str).await?;
+
+        let ingest_secret_hash = ingest_secret.map(hash_ingest_secret);
+        client
+            .execute(&stmt, &[&project_id, &ingest_secret_hash])
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_retention_days(
+        client: &Client,
+        project_id: i32,
+        retention_days: Option<i32>,
+    ) -> Result<(), DataError> {
+        if retention_days.is_some_and(|days| days <= 0) {
+            return Err(DataError::InvalidSettings(
+                "retention_days must be positive".to_string(),
+            ));
+        }
+
+        let stmt_str = include_str!("../../sql/set_retention_days.sql");
+        let stmt = client.prepare(stmt_str).await?;
+
+        client
+            .execute(&stmt, &[&project_id, &retention_days])
+            .await?;
+        Ok(())
+    }
+
+    pub async fn revoke_access_key(client: &Client, project_id: i32) -> Result<(), DataError> {
+        let stmt_str = include_str!("../../sql/revoke_access_key.sql");
+        let stmt = client.prepare(stmt_str).await?;
+
+        client.execute(&stmt, &[&project_id]).await?;
+        Ok(())
+    }
+
+    /// Fails with `DataError::Forbidden` unless the user is a member of the project.
+    pub async fn check_membership(
+        client: &Client,
+        user_id: i32,
+        project_id: i32,
+    ) -> Result<(), DataError> {
+        let stmt_str = include_str!("../../sql/is_member_of_project.sql");
+        let stmt = client.prepare(stmt_str).await?;
+
+        let is_member: bool = client
+            .query_one(&stmt, &[&user_id, &project_id])
+            .await?
+            .get("is_member");
+        if is_member {
+            Ok(())
+        } else {
+            Err(DataError::Forbidden)
+        }
     }
 
     pub async fn save_project(
@@ -71,19 +187,26 @@ impl Project {
 
         Ok(saved_project)
     }
+}
 
-    pub async fn get_tags(
-        client: &Client,
-        access_key: uuid::Uuid,
-    ) -> Result<Vec<String>, DataError> {
-        let stmt_str = include_str!("../../sql/get_tags_from_access_key.sql");
-        let stmt = client.prepare(stmt_str).await?;
+/// The SHA-256 of an ingest secret, hex encoded. Secrets are long and random, a salt or a slow
+/// hash wouldn't make them harder to guess.
+pub fn hash_ingest_secret(ingest_secret: &str) -> String {
+    openssl::sha::sha256(ingest_secret.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
 
-        let rows = client.query(&stmt, &[&access_key]).await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Ok(rows
-            .iter()
-            .map(|row| row.get("name"))
-            .collect::<Vec<String>>())
+    #[test]
+    fn ingest_secrets_are_hashed_like_the_migration_does() {
+        assert_eq!(
+            hash_ingest_secret("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
     }
 }
@@ -0,0 +1,91 @@
+use crate::db::reports::ReportInfo;
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// `main.tags.name` is a `varchar(100)`.
+pub const MAX_TAG_LEN: usize = 100;
+pub const MAX_TAGS_PER_REPORT: usize = 50;
+/// The most bytes the properties of a report take as JSON.
+pub const MAX_PROPERTIES_LEN: usize = 4096;
+/// Reports whose corrected time is this far ahead of the server's clock are saved at the
+/// server's time, further ahead they are rejected.
+pub const MAX_CLOCK_SKEW_MS: i64 = 5 * 60 * 1000;
+/// Reports whose corrected time is older than a week are rejected.
+pub const MAX_REPORT_AGE_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+
+/// Something wrong with a field of a report.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ReportProblem {
+    /// The field, `tags[2]` for the third tag.
+    pub field: String,
+    pub message: String,
+}
+
+impl ReportProblem {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        ReportProblem {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Trims and dedupes the tags of the report, then returns every problem left with it. The time
+/// is checked once corrected by `clock_skew`.
+pub fn normalize_report(
+    report_info: &mut ReportInfo,
+    now_ms: i64,
+    clock_skew: i64,
+) -> Vec<ReportProblem> {
+    let mut problems = Vec::new();
+
+    let mut seen = HashSet::new();
+    let mut tags = Vec::with_capacity(report_info.tags.len());
+    for (i, tag_name) in report_info.tags.iter().enumerate() {
+        let tag_name = tag_name.trim();
+        let field = format!("tags[{}]", i);
+
+        if tag_name.is_empty() {
+            problems.push(ReportProblem::new(field, "tag is blank"));
+        } else if tag_name.chars().count() > MAX_TAG_LEN {
+            problems.push(ReportProblem::new(
+                field,
+                format!("tag is longer than {} characters", MAX_TAG_LEN),
+            ));
+        } else if seen.insert(tag_name) {
+            tags.push(tag_name.to_string());
+        }
+    }
+
+    if tags.is_empty() && problems.is_empty() {
+        problems.push(ReportProblem::new(
+            "tags",
+            "a report needs at least one tag",
+        ));
+    }
+    if tags.len() > MAX_TAGS_PER_REPORT {
+        problems.push(ReportProblem::new(
+            "tags",
+            format!("a report can have at most {} tags", MAX_TAGS_PER_REPORT),
+        ));
+    }
+    report_info.tags = tags;
+
+    if !report_info.properties.is_object() {
+        problems.push(ReportProblem::new("properties", "must be a JSON object"));
+    } else if report_info.properties.to_string().len() > MAX_PROPERTIES_LEN {
+        problems.push(ReportProblem::new(
+            "properties",
+            format!("longer than {} bytes", MAX_PROPERTIES_LEN),
+        ));
+    }
+
+    let corrected_time_ms = report_info.time_ms.saturating_add(clock_skew);
+    if corrected_time_ms > now_ms + MAX_CLOCK_SKEW_MS {
+        problems.push(ReportProblem::new("time_ms", "too far in the future"));
+    } else if corrected_time_ms < now_ms - MAX_REPORT_AGE_MS {
+        problems.push(ReportProblem::new("time_ms", "too far in the past"));
+    }
+
+    problems
+}
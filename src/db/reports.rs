@@ -1,32 +1,33 @@
+use crate::db::report_validation::{normalize_report, ReportProblem};
 use crate::dberror::DataError;
-use actix_web::{Error, HttpRequest, HttpResponse, Responder};
-use deadpool_postgres::Client;
-use futures::future::{ready, AndThen, Ready};
-use futures::{Future, TryFutureExt, TryStreamExt};
-use postgres_types::FromSql;
-use postgres_types::Type;
+use deadpool_postgres::{Client, Transaction};
 use serde::{Deserialize, Serialize};
 use tokio_pg_mapper::FromTokioPostgresRow;
 use tokio_pg_mapper_derive::PostgresMapper;
-use tokio_postgres::Row;
+use std::collections::{HashMap, HashSet};
 
-#[derive(PostgresMapper)]
-#[pg_mapper(table = "reports")]
-pub struct Report {
-    pub report_id: i32,
-    pub session_id: uuid::Uuid,
-    pub project_id: i32,
-    pub timestamp: i64,
-}
+/// The maximum number of reports accepted in one batch.
+pub const MAX_BATCH_SIZE: usize = 500;
 
 #[derive(Serialize, Deserialize, PostgresMapper, Clone, Debug)]
 #[pg_mapper(table = "users")]
 pub struct ReportInfo {
-    #[serde(skip_serializing)]
+    /// Can be left out when the access key is sent in the `X-Access-Key` header.
+    #[serde(default, skip_serializing)]
     pub access_key: uuid::Uuid,
     pub session_id: uuid::Uuid,
     pub time_ms: i64,
     pub tags: Vec<String>,
+    /// Values describing the report (page url, element id, ...), a JSON object.
+    #[serde(default = "no_properties")]
+    pub properties: serde_json::Value,
+    /// When the client sent the report, by its clock. It tells how far off the clock is.
+    #[serde(default, skip_serializing)]
+    pub sent_at: Option<i64>,
+}
+
+fn no_properties() -> serde_json::Value {
+    serde_json::Value::Object(serde_json::Map::new())
 }
 
 #[derive(Serialize, Deserialize, PostgresMapper)]
@@ -36,98 +37,264 @@ pub struct Tag {
     pub name: String,
 }
 
-impl Report {
-    pub async fn into_report_info(self, client: &Client) -> Result<ReportInfo, DataError> {
-        let tags = self.get_tags(client).await?;
+impl ReportInfo {
+    pub async fn save_report(
+        client: &mut Client,
+        report_info: ReportInfo,
+        project_id: i32,
+    ) -> Result<(), DataError> {
+        let mut project_ids = HashMap::new();
+        project_ids.insert(report_info.access_key, project_id);
+
+        let summary = Self::save_reports(client, vec![report_info], &project_ids).await?;
 
-        Ok(ReportInfo {
-            access_key: uuid::Uuid::nil(),
-            session_id: self.session_id,
-            time_ms: self.timestamp,
-            tags: tags.into_iter().map(|t| t.name).collect(),
-        })
+        match summary.items.into_iter().next() {
+            Some(BatchItemStatus::Rejected(problems)) => Err(DataError::InvalidReport(problems)),
+            _ => Ok(()),
+        }
     }
 
-    pub async fn get_tags(&self, client: &Client) -> Result<Vec<Tag>, DataError> {
-        let stmt_str = include_str!("../../sql/get_tags_of_report.sql");
-        let stmt = client.prepare(&stmt_str).await?;
+    /// Saves every acceptable report in one transaction. Reports are normalized and rejected
+    /// one by one (access key not in `project_ids`, invalid tags, ...) so that a bad item
+    /// doesn't fail the whole batch.
+    pub async fn save_reports(
+        client: &mut Client,
+        mut reports: Vec<ReportInfo>,
+        project_ids: &HashMap<uuid::Uuid, i32>,
+    ) -> Result<BatchSummary, DataError> {
+        let now_ms = chrono::Utc::now().timestamp_millis();
 
-        Ok(client
-            .query(&stmt, &[&self.report_id])
-            .await?
+        let transaction = client.transaction().await?;
+
+        let clock_skews =
+            Self::session_clock_skews(&transaction, &reports, project_ids, now_ms).await?;
+        let items = reports
+            .iter_mut()
+            .map(|report_info| {
+                // reports with an unknown access key are rejected anyway, they get no skew
+                let clock_skew = project_ids
+                    .get(&report_info.access_key)
+                    .and_then(|project_id| clock_skews.get(&(*project_id, report_info.session_id)))
+                    .copied()
+                    .unwrap_or(0);
+                Self::check_report(report_info, project_ids, now_ms, clock_skew)
+            })
+            .collect::<Vec<BatchItemStatus>>();
+
+        let accepted_reports = reports
             .iter()
-            .map(|row| Tag::from_row_ref(row).unwrap())
-            .collect::<Vec<Tag>>())
-    }
-    
-    pub async fn get_reports_of_session(
-        client: &Client,
-        session_id: uuid::Uuid,
-    ) -> Result<Vec<Report>, DataError> {
-        let stmt_str = include_str!("../../sql/get_reports_of_session.sql");
-        let stmt = client.prepare(&stmt_str).await?;
-
-        Ok(client
-            .query(&stmt, &[&session_id])
-            .await?
+            .zip(&items)
+            .filter(|(_, status)| **status == BatchItemStatus::Accepted)
+            .map(|(report_info, _)| report_info)
+            .collect::<Vec<&ReportInfo>>();
+
+        let tag_names = accepted_reports
             .iter()
-            .map(|row| Report::from_row_ref(row).unwrap())
-            .collect::<Vec<Report>>())
-    }
-    
-    pub async fn save_report(client: &Client, report_info: ReportInfo) -> Result<(), DataError> {
-        let stmt_str = include_str!("../../sql/insert_report.sql");
-        let stmt = client.prepare(&stmt_str).await?;
-
-        let report_id = client
-            .query_one(
-                &stmt,
-                &[
-                    &report_info.access_key,
-                    &report_info.session_id,
-                    &report_info.time_ms,
-                ],
-            )
-            .await?
-            .get("report_id");
-        Self::save_tags_to_report(client, report_info.tags, report_id).await?;
+            .flat_map(|report_info| report_info.tags.iter().cloned())
+            .collect::<HashSet<String>>()
+            .into_iter()
+            .collect::<Vec<String>>();
+
+        let tag_ids = Self::upsert_tags(&transaction, &tag_names).await?;
+
+        let insert_report = transaction
+            .prepare(include_str!("../../sql/insert_report.sql"))
+            .await?;
+        let save_tags = transaction
+            .prepare(include_str!("../../sql/save_tags_to_report.sql"))
+            .await?;
+
+        for report_info in accepted_reports {
+            let project_id = project_ids[&report_info.access_key];
+            let corrected_time_ms = report_info
+                .time_ms
+                .saturating_add(clock_skews[&(project_id, report_info.session_id)])
+                .min(now_ms);
+            let report_id: i32 = transaction
+                .query_one(
+                    &insert_report,
+                    &[
+                        &project_id,
+                        &report_info.session_id,
+                        &report_info.time_ms,
+                        &report_info.properties,
+                        &now_ms,
+                        &corrected_time_ms,
+                    ],
+                )
+                .await?
+                .get("report_id");
+
+            let report_tag_ids = report_info
+                .tags
+                .iter()
+                .map(|tag_name| {
+                    tag_ids
+                        .get(tag_name)
+                        .copied()
+                        .ok_or_else(|| DataError::MissingTag(tag_name.clone()))
+                })
+                .collect::<Result<Vec<i32>, DataError>>()?;
+
+            transaction
+                .execute(&save_tags, &[&report_id, &report_tag_ids])
+                .await?;
+        }
 
-        Ok(())
+        transaction.commit().await?;
+
+        Ok(BatchSummary::new(items))
     }
 
-    async fn save_tags_to_report(
-        client: &Client,
-        tags: Vec<String>,
-        report_id: i32,
-    ) -> Result<(), DataError> {
-        let futures = tags
+    /// The clock skew of each session of the reports with a known access key, by project. A
+    /// session keeps the skew estimated from the first batch it was seen in, so that its reports
+    /// are all corrected alike.
+    async fn session_clock_skews(
+        transaction: &Transaction<'_>,
+        reports: &[ReportInfo],
+        project_ids: &HashMap<uuid::Uuid, i32>,
+        now_ms: i64,
+    ) -> Result<HashMap<(i32, uuid::Uuid), i64>, DataError> {
+        let reports = reports
             .iter()
-            .map(|tag_name| Self::save_tag_to_report(client, tag_name, report_id));
+            .filter_map(|report_info| {
+                project_ids
+                    .get(&report_info.access_key)
+                    .map(|project_id| (*project_id, report_info))
+            })
+            .collect::<Vec<(i32, &ReportInfo)>>();
+        if reports.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut clock_skews = Self::estimate_clock_skews(&reports, now_ms);
+        let mut project_ids = vec![];
+        let mut session_ids = vec![];
+        let mut estimates = vec![];
+        for (&(project_id, session_id), &clock_skew) in &clock_skews {
+            project_ids.push(project_id);
+            session_ids.push(session_id);
+            estimates.push(clock_skew);
+        }
+
+        let stmt_str = include_str!("../../sql/upsert_session_clock_skews.sql");
+        let stmt = transaction.prepare(stmt_str).await?;
+
+        let stored = transaction
+            .query(&stmt, &[&project_ids, &session_ids, &estimates, &now_ms])
+            .await?;
+        for row in stored {
+            clock_skews.insert(
+                (row.get("project_id"), row.get("session_id")),
+                row.get("clock_skew"),
+            );
+        }
 
-        let b = futures::future::try_join_all(futures).await?;
-        Ok(())
+        Ok(clock_skews)
     }
 
-    async fn save_tag_to_report(
-        client: &Client,
-        tag_name: &String,
-        report_id: i32,
-    ) -> Result<(), DataError> {
-        let stmt_str = include_str!("../../sql/save_tag_to_report.sql");
-        let stmt = client.prepare(&stmt_str).await?;
+    /// Estimates how many millisecs the clock of each session is behind the server's, from
+    /// the median of `now_ms - sent_at` of its reports. Without `sent_at`, a clock is only known
+    /// to be off when it's ahead, the session's latest report is then taken as sent at `now_ms`.
+    fn estimate_clock_skews(
+        reports: &[(i32, &ReportInfo)],
+        now_ms: i64,
+    ) -> HashMap<(i32, uuid::Uuid), i64> {
+        let mut offsets = HashMap::<(i32, uuid::Uuid), Vec<i64>>::new();
+        let mut latest_times = HashMap::<(i32, uuid::Uuid), i64>::new();
+        for &(project_id, report_info) in reports {
+            let session = (project_id, report_info.session_id);
+            let session_offsets = offsets.entry(session).or_default();
+            if let Some(sent_at) = report_info.sent_at {
+                session_offsets.push(now_ms.saturating_sub(sent_at));
+            }
+            let latest_time = latest_times.entry(session).or_insert(report_info.time_ms);
+            *latest_time = (*latest_time).max(report_info.time_ms);
+        }
+
+        offsets
+            .into_iter()
+            .map(|(session, mut session_offsets)| {
+                let clock_skew = if session_offsets.is_empty() {
+                    now_ms.saturating_sub(latest_times[&session]).min(0)
+                } else {
+                    session_offsets.sort();
+                    session_offsets[session_offsets.len() / 2]
+                };
+                (session, clock_skew)
+            })
+            .collect()
+    }
+
+    fn check_report(
+        report_info: &mut ReportInfo,
+        project_ids: &HashMap<uuid::Uuid, i32>,
+        now_ms: i64,
+        clock_skew: i64,
+    ) -> BatchItemStatus {
+        let mut problems = normalize_report(report_info, now_ms, clock_skew);
+        if !project_ids.contains_key(&report_info.access_key) {
+            problems.insert(
+                0,
+                ReportProblem::new("access_key", "unknown or revoked access key"),
+            );
+        }
+
+        if problems.is_empty() {
+            BatchItemStatus::Accepted
+        } else {
+            BatchItemStatus::Rejected(problems)
+        }
+    }
 
-        let tag = Self::get_or_insert_tag(client, &tag_name).await?;
+    /// Inserts the tags that don't exist yet and returns the ids of all of them, also of the
+    /// ones inserted meanwhile by other transactions.
+    async fn upsert_tags(
+        transaction: &Transaction<'_>,
+        tag_names: &[String],
+    ) -> Result<HashMap<String, i32>, DataError> {
+        if tag_names.is_empty() {
+            return Ok(HashMap::new());
+        }
 
-        client.execute(&stmt, &[&report_id, &tag.tag_id]).await?;
+        let stmt_str = include_str!("../../sql/upsert_tags.sql");
+        let stmt = transaction.prepare(stmt_str).await?;
 
-        Ok(())
+        Ok(transaction
+            .query(&stmt, &[&tag_names])
+            .await?
+            .iter()
+            .map(|row| Tag::from_row_ref(row).unwrap())
+            .map(|tag| (tag.name, tag.tag_id))
+            .collect())
     }
+}
 
-    async fn get_or_insert_tag(client: &Client, tag_name: &String) -> Result<Tag, DataError> {
-        let stmt_str = include_str!("../../sql/get_or_insert_tag.sql");
-        let stmt = client.prepare(stmt_str).await?;
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "status", content = "problems", rename_all = "snake_case")]
+pub enum BatchItemStatus {
+    Accepted,
+    Rejected(Vec<ReportProblem>),
+}
+
+#[derive(Serialize, Debug)]
+pub struct BatchSummary {
+    pub accepted: usize,
+    pub rejected: usize,
+    pub items: Vec<BatchItemStatus>,
+}
+
+impl BatchSummary {
+    fn new(items: Vec<BatchItemStatus>) -> Self {
+        let accepted = items
+            .iter()
+            .filter(|status| **status == BatchItemStatus::Accepted)
+            .count();
 
-        let row = client.query_one(&stmt, &[&tag_name, &tag_name]).await?;
-        Ok(Tag::from_row_ref(&row).unwrap())
+        BatchSummary {
+            accepted,
+            rejected: items.len() - accepted,
+            items,
+        }
     }
 }
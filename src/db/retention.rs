@@ -0,0 +1,178 @@
+use crate::db::report_validation::MAX_REPORT_AGE_MS;
+use crate::db::{blocking, rollups};
+use crate::dberror::DataError;
+use deadpool_postgres::{Client, Pool, Transaction};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+
+/// The most reports deleted in one transaction, so that the table is never locked for long.
+const RETENTION_BATCH_SIZE: i64 = 1000;
+
+/// A report past its project's retention window, as it's written to the archive.
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table = "reports")]
+pub struct ExpiredReport {
+    pub report_id: i32,
+    pub project_id: i32,
+    pub session_id: uuid::Uuid,
+    pub timestamp: i64,
+    pub received_at: i64,
+    pub corrected_timestamp: i64,
+    pub properties: serde_json::Value,
+    pub tags: Vec<String>,
+}
+
+/// Deletes the reports received before the retention window of their project, one batch per
+/// transaction until none are left. With `archive_dir`, every batch is first written there as
+/// gzipped JSON lines, in a `.partial` file renamed once the batch is deleted. Returns the
+/// number of reports deleted.
+///
+/// Also forgets the clock skews of the sessions unseen for longer than reports are accepted.
+pub async fn purge_expired_reports(
+    pool: &Pool,
+    archive_dir: Option<&Path>,
+) -> Result<u64, DataError> {
+    let mut client: Client = pool.get().await.map_err(DataError::PoolError)?;
+    let now_ms = chrono::Utc::now().timestamp_millis();
+
+    let mut deleted = 0;
+    loop {
+        let transaction = client.transaction().await?;
+
+        let stmt = transaction
+            .prepare(include_str!("../../sql/get_expired_reports.sql"))
+            .await?;
+        let reports = transaction
+            .query(&stmt, &[&now_ms, &RETENTION_BATCH_SIZE])
+            .await?
+            .iter()
+            .map(|row| ExpiredReport::from_row_ref(row).unwrap())
+            .collect::<Vec<ExpiredReport>>();
+        if reports.is_empty() {
+            break;
+        }
+
+        let batch_len = reports.len() as i64;
+        let report_ids = reports
+            .iter()
+            .map(|report| report.report_id)
+            .collect::<Vec<i32>>();
+        let mut session_ids = reports
+            .iter()
+            .map(|report| report.session_id)
+            .collect::<Vec<uuid::Uuid>>();
+        session_ids.sort_unstable();
+        session_ids.dedup();
+
+        let archive = match archive_dir {
+            Some(archive_dir) => {
+                let archive_dir = archive_dir.to_path_buf();
+                Some(blocking(move || archive_reports(&archive_dir, now_ms, &reports)).await?)
+            }
+            None => None,
+        };
+
+        match delete_reports(transaction, &report_ids, &session_ids).await {
+            Ok(batch_deleted) => deleted += batch_deleted,
+            Err(err) => {
+                if let Some(partial_path) = archive {
+                    // the reports are still there, their archive isn't one
+                    let _ = blocking(move || Ok(fs::remove_file(partial_path)?)).await;
+                }
+                return Err(err);
+            }
+        }
+
+        if let Some(partial_path) = archive {
+            blocking(move || {
+                let path = partial_path.with_extension("");
+                Ok(fs::rename(partial_path, path)?)
+            })
+            .await?;
+        }
+
+        if batch_len < RETENTION_BATCH_SIZE {
+            break;
+        }
+    }
+
+    let stmt = client
+        .prepare(include_str!("../../sql/delete_stale_clock_skews.sql"))
+        .await?;
+    client
+        .execute(&stmt, &[&(now_ms - MAX_REPORT_AGE_MS)])
+        .await?;
+
+    Ok(deleted)
+}
+
+/// Deletes the reports of the sessions and recomputes the rollups of the days they started on.
+async fn delete_reports(
+    transaction: Transaction<'_>,
+    report_ids: &[i32],
+    session_ids: &[uuid::Uuid],
+) -> Result<u64, DataError> {
+    let days = rollups::get_session_days(&transaction, session_ids).await?;
+
+    let stmt = transaction
+        .prepare(include_str!("../../sql/delete_reports.sql"))
+        .await?;
+    let deleted = transaction.execute(&stmt, &[&report_ids]).await?;
+
+    rollups::roll_up_days(&transaction, session_ids, days).await?;
+
+    transaction.commit().await?;
+
+    Ok(deleted)
+}
+
+/// Writes the reports to a new `.partial` file of `archive_dir`, named after the time of the
+/// purge and the first report of the batch, and returns its path.
+fn archive_reports(
+    archive_dir: &Path,
+    now_ms: i64,
+    reports: &[ExpiredReport],
+) -> Result<PathBuf, DataError> {
+    let path = archive_dir.join(format!(
+        "reports-{}-{}.jsonl.gz.partial",
+        now_ms, reports[0].report_id
+    ));
+    let mut encoder = GzEncoder::new(File::create(&path)?, Compression::default());
+    for report in reports {
+        serde_json::to_writer(&mut encoder, report)
+            .map_err(|err| DataError::IoError(err.into()))?;
+        encoder.write_all(b"\n")?;
+    }
+    encoder.finish()?.sync_all()?;
+
+    Ok(path)
+}
+
+/// Deletes every report of the session and recomputes the rollups of the day it started on,
+/// returning how many reports there were.
+pub async fn purge_session(
+    client: &mut Client,
+    project_id: i32,
+    session_id: uuid::Uuid,
+) -> Result<u64, DataError> {
+    let transaction = client.transaction().await?;
+    let session_ids = [session_id];
+    let days = rollups::get_session_days(&transaction, &session_ids).await?;
+
+    let stmt_str = include_str!("../../sql/delete_session_reports.sql");
+    let stmt = transaction.prepare(stmt_str).await?;
+    let deleted = transaction
+        .execute(&stmt, &[&project_id, &session_id])
+        .await?;
+
+    rollups::roll_up_days(&transaction, &session_ids, days).await?;
+    transaction.commit().await?;
+
+    Ok(deleted)
+}
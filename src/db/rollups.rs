@@ -0,0 +1,269 @@
+use crate::db::sessions::{Clock, Session, SessionFilter};
+use crate::dberror::DataError;
+use deadpool_postgres::{Client, Pool, Transaction};
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+
+pub const MS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// Reports are received a little before their transaction commits, the reports received this
+/// long before the last run are looked at again.
+const RECEIVED_MARGIN_MS: i64 = 5 * 60 * 1000;
+
+#[derive(PostgresMapper)]
+#[pg_mapper(table = "rollup_state")]
+struct RollupState {
+    received_until: i64,
+    days_until: i64,
+}
+
+#[derive(PostgresMapper)]
+#[pg_mapper(table = "daily_rollups")]
+struct RollupTotals {
+    sessions: i64,
+    duration_sum: i64,
+}
+
+/// Rolls up the completed days whose sessions got reports since the last run, returning how
+/// many days were rolled up. Does nothing while another server is at it.
+pub async fn roll_up(pool: &Pool) -> Result<usize, DataError> {
+    let mut client: Client = pool.get().await.map_err(DataError::PoolError)?;
+    let transaction = client.transaction().await?;
+
+    let stmt = transaction
+        .prepare(include_str!("../../sql/lock_rollup_state.sql"))
+        .await?;
+    let state = match transaction.query(&stmt, &[]).await?.first() {
+        Some(row) => RollupState::from_row_ref(row)?,
+        None => return Ok(0),
+    };
+
+    let now_ms = chrono::Utc::now().timestamp_millis();
+    let today_start = now_ms - now_ms % MS_PER_DAY;
+    let received_since = (state.received_until - RECEIVED_MARGIN_MS).max(0);
+
+    let stmt = transaction
+        .prepare(include_str!("../../sql/get_days_to_roll_up.sql"))
+        .await?;
+    let days = transaction
+        .query(&stmt, &[&received_since, &today_start])
+        .await?
+        .iter()
+        .map(|row| (row.get("project_id"), row.get("day_start")))
+        .collect::<Vec<(i32, i64)>>();
+
+    let stmt = transaction
+        .prepare(include_str!("../../sql/roll_up_day.sql"))
+        .await?;
+    for (project_id, day_start) in &days {
+        transaction.execute(&stmt, &[project_id, day_start]).await?;
+    }
+
+    let stmt = transaction
+        .prepare(include_str!("../../sql/update_rollup_state.sql"))
+        .await?;
+    transaction.execute(&stmt, &[&now_ms, &today_start]).await?;
+
+    transaction.commit().await?;
+
+    Ok(days.len())
+}
+
+/// The days before this one are rolled up.
+pub async fn days_until(client: &Client) -> Result<i64, DataError> {
+    let stmt = client
+        .prepare(include_str!("../../sql/get_rollup_state.sql"))
+        .await?;
+    let row = client.query_one(&stmt, &[]).await?;
+
+    Ok(RollupState::from_row_ref(&row)?.days_until)
+}
+
+/// The names of every tag the project's reports were sent with, as the project shows them,
+/// hidden ones left out.
+pub async fn get_tags(client: &Client, project_id: i32) -> Result<Vec<String>, DataError> {
+    let days_until = days_until(client).await?;
+
+    let stmt = client
+        .prepare(include_str!("../../sql/get_tags_of_project.sql"))
+        .await?;
+
+    Ok(client
+        .query(&stmt, &[&project_id, &days_until])
+        .await?
+        .iter()
+        .map(|row| row.get("name"))
+        .collect::<Vec<String>>())
+}
+
+/// Which parts of the filter's range are served from rollups, and which from raw reports.
+struct SplitRange {
+    /// The rolled up days, `[start, end)`.
+    days: Option<(i64, i64)>,
+    /// The rest of the range, `[from, to)`.
+    raw: Vec<(Option<i64>, Option<i64>)>,
+}
+
+impl SplitRange {
+    /// `None` when the filter can't be served like the rollups, which only know about the
+    /// client's clock and can't filter by property.
+    async fn new(client: &Client, filter: &SessionFilter) -> Result<Option<SplitRange>, DataError> {
+        if filter.property.is_some() || filter.clock != Clock::Client {
+            return Ok(None);
+        }
+
+        let days_start = match filter.from {
+            Some(from) => {
+                let from = from
+                    .checked_add(MS_PER_DAY - 1)
+                    .ok_or_else(|| DataError::InvalidFilter("from is out of range".to_string()))?;
+                from.div_euclid(MS_PER_DAY) * MS_PER_DAY
+            }
+            None => 0,
+        };
+        let days_until = days_until(client).await?;
+        let days_end = match filter.to {
+            Some(to) => (to.div_euclid(MS_PER_DAY) * MS_PER_DAY).min(days_until),
+            None => days_until,
+        };
+        if days_start >= days_end {
+            return Ok(Some(SplitRange {
+                days: None,
+                raw: vec![(filter.from, filter.to)],
+            }));
+        }
+
+        let mut raw = Vec::new();
+        if filter.from.is_none_or(|from| from < days_start) {
+            raw.push((filter.from, Some(days_start)));
+        }
+        if filter.to.is_none_or(|to| to > days_end) {
+            raw.push((Some(days_end), filter.to));
+        }
+
+        Ok(Some(SplitRange {
+            days: Some((days_start, days_end)),
+            raw,
+        }))
+    }
+
+    /// The totals of the sessions that started in the range.
+    async fn get_totals(
+        &self,
+        client: &Client,
+        project_id: i32,
+    ) -> Result<RollupTotals, DataError> {
+        let mut totals = RollupTotals {
+            sessions: 0,
+            duration_sum: 0,
+        };
+
+        if let Some((days_start, days_end)) = self.days {
+            let stmt = client
+                .prepare(include_str!("../../sql/get_rollup_totals.sql"))
+                .await?;
+            let row = client
+                .query_one(&stmt, &[&project_id, &days_start, &days_end])
+                .await?;
+            totals.add(RollupTotals::from_row_ref(&row)?);
+        }
+
+        let stmt = client
+            .prepare(include_str!("../../sql/get_started_sessions_totals.sql"))
+            .await?;
+        for (from, to) in &self.raw {
+            let row = client.query_one(&stmt, &[&project_id, from, to]).await?;
+            totals.add(RollupTotals::from_row_ref(&row)?);
+        }
+
+        Ok(totals)
+    }
+}
+
+impl RollupTotals {
+    fn add(&mut self, other: RollupTotals) {
+        self.sessions += other.sessions;
+        self.duration_sum += other.duration_sum;
+    }
+}
+
+/// Counts the sessions that started in the filter's range, from the rollups of the completed
+/// days and the reports of the rest. Filters the rollups can't serve are counted from the
+/// reports by `Session::get_sessions_count`, which counts the same sessions.
+pub async fn get_sessions_count(
+    client: &Client,
+    project_id: i32,
+    filter: &SessionFilter,
+) -> Result<i64, DataError> {
+    match SplitRange::new(client, filter).await? {
+        Some(split) => Ok(split.get_totals(client, project_id).await?.sessions),
+        None => Session::get_sessions_count(client, project_id, filter).await,
+    }
+}
+
+/// The mean duration in millisecs of the sessions that started in the filter's range, from
+/// their first to their last report, served like `get_sessions_count`. Filters the rollups
+/// can't serve get the mean of `Session::get_duration_stats`, of the same sessions.
+pub async fn get_mean_session_duration(
+    client: &Client,
+    project_id: i32,
+    filter: &SessionFilter,
+) -> Result<i64, DataError> {
+    match SplitRange::new(client, filter).await? {
+        Some(split) => {
+            let totals = split.get_totals(client, project_id).await?;
+            Ok(totals
+                .duration_sum
+                .checked_div(totals.sessions)
+                .unwrap_or(0))
+        }
+        None => Ok(Session::get_duration_stats(client, project_id, filter)
+            .await?
+            .mean),
+    }
+}
+
+/// The rolled up days that the sessions started on, to recompute with `roll_up_days` once
+/// their reports are deleted. Waits for a running `roll_up` and keeps the next one out until
+/// the transaction ends, so that it doesn't roll up the reports being deleted.
+pub async fn get_session_days(
+    transaction: &Transaction<'_>,
+    session_ids: &[uuid::Uuid],
+) -> Result<Vec<(i32, i64)>, DataError> {
+    let stmt = transaction
+        .prepare(include_str!("../../sql/wait_for_rollup_state.sql"))
+        .await?;
+    let days_until =
+        RollupState::from_row_ref(&transaction.query_one(&stmt, &[]).await?)?.days_until;
+
+    let stmt = transaction
+        .prepare(include_str!("../../sql/get_session_start_days.sql"))
+        .await?;
+    Ok(transaction
+        .query(&stmt, &[&session_ids, &days_until])
+        .await?
+        .iter()
+        .map(|row| (row.get("project_id"), row.get("day_start")))
+        .collect())
+}
+
+/// Recomputes the rollups of the days the sessions started on before their reports were
+/// deleted, and of the days the rest of their reports start on now.
+pub async fn roll_up_days(
+    transaction: &Transaction<'_>,
+    session_ids: &[uuid::Uuid],
+    mut days: Vec<(i32, i64)>,
+) -> Result<(), DataError> {
+    days.extend(get_session_days(transaction, session_ids).await?);
+    days.sort_unstable();
+    days.dedup();
+
+    let stmt = transaction
+        .prepare(include_str!("../../sql/roll_up_day.sql"))
+        .await?;
+    for (project_id, day_start) in &days {
+        transaction.execute(&stmt, &[project_id, day_start]).await?;
+    }
+
+    Ok(())
+}
@@ -1,16 +1,20 @@
 use crate::db::percentage::Percentage;
-use crate::db::reports::{Report, ReportInfo};
+use crate::db::reports::ReportInfo;
+use crate::db::tag_rules::{TagMatcher, TagRule};
 use crate::dberror::DataError;
-use actix_cors::Cors;
 use actix_web::{Error, HttpRequest, HttpResponse, Responder};
 use deadpool_postgres::Client;
 use futures::future::{ready, Ready};
-use futures::Stream;
+use futures::TryStreamExt;
+use postgres_types::ToSql;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 use std::time::Duration;
-
-pub type ProjectStats = Vec<Step>;
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Step {
@@ -19,23 +23,261 @@ pub struct Step {
     pub duration: Duration,
 }
 
-#[derive(Clone, Serialize, Deserialize, Eq, PartialEq, Hash)]
+/// A group of reports, matched by `rule`, or by having one of `tags_names` if it has no rule.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "TagGroupDefinition", into = "TagGroupDefinition")]
 pub struct TagGroup {
     pub id: i32,
+    tags_names: Vec<String>,
+    rule: Option<TagRule>,
+    matcher: Arc<TagMatcher>,
+}
+
+/// How tag groups are sent and stored, `TagGroup` also holds the compiled rule.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TagGroupDefinition {
+    pub id: i32,
+    #[serde(default)]
     pub tags_names: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<TagRule>,
 }
 
+/// The id of the group of reports that match none of the tag groups.
+pub const OTHER_TAG_GROUP_ID: i32 = -1;
+
 impl TagGroup {
-    pub fn contains_any(&self, tags: &[String]) -> bool {
-        for self_tag in &self.tags_names {
-            for other_tag in tags {
-                if self_tag == other_tag {
-                    return true;
-                }
-            }
+    pub fn new(id: i32, tags_names: Vec<String>, rule: Option<TagRule>) -> Result<Self, DataError> {
+        let matcher = match &rule {
+            Some(rule) => rule.compile()?,
+            None => TagMatcher::Any(tags_names.iter().cloned().collect()),
+        };
+
+        Ok(TagGroup {
+            id,
+            tags_names,
+            rule,
+            matcher: Arc::new(matcher),
+        })
+    }
+
+    pub fn other() -> Self {
+        TagGroup {
+            id: OTHER_TAG_GROUP_ID,
+            tags_names: vec![],
+            rule: None,
+            matcher: Arc::new(TagMatcher::Any(HashSet::new())),
+        }
+    }
+
+    /// Whether a report with `tags` belongs to the group.
+    pub fn matches(&self, tags: &[String]) -> bool {
+        self.matcher.matches(tags)
+    }
+}
+
+impl TryFrom<TagGroupDefinition> for TagGroup {
+    type Error = DataError;
+
+    fn try_from(definition: TagGroupDefinition) -> Result<Self, Self::Error> {
+        TagGroup::new(definition.id, definition.tags_names, definition.rule)
+    }
+}
+
+impl From<TagGroup> for TagGroupDefinition {
+    fn from(tag_group: TagGroup) -> Self {
+        TagGroupDefinition {
+            id: tag_group.id,
+            tags_names: tag_group.tags_names,
+            rule: tag_group.rule,
+        }
+    }
+}
+
+// the matcher is derived from the other fields
+impl PartialEq for TagGroup {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id && self.tags_names == other.tags_names && self.rule == other.rule
+    }
+}
+
+impl Eq for TagGroup {}
+
+impl Hash for TagGroup {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+        self.tags_names.hash(state);
+        self.rule.hash(state);
+    }
+}
+
+/// 30 minutes, the usual idle timeout of web analytics.
+const DEFAULT_IDLE_TIMEOUT_MS: i64 = 30 * 60 * 1000;
+
+/// Limits sessions to their reports sent in `[from, to)`, in milliseconds since the epoch.
+/// A gap longer than `idle_timeout` milliseconds between two reports of a session starts a
+/// new visit. The analytics only split sessions into their visits with `split_visits`.
+///
+/// With `property`, only the sessions with a report in the time range whose property passes
+/// `property_op` against `property_value` are kept, e.g.
+/// `?property=url&property_op=starts_with&property_value=/checkout`.
+///
+/// Times are taken from `clock`, for the range as well as for the reports.
+#[derive(Deserialize, Default)]
+pub struct SessionFilter {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub idle_timeout: Option<i64>,
+    #[serde(default)]
+    pub split_visits: bool,
+    pub property: Option<String>,
+    pub property_op: Option<PropertyOp>,
+    pub property_value: Option<String>,
+    #[serde(default)]
+    pub clock: Clock,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Clock {
+    /// The times the clients sent.
+    #[default]
+    Client,
+    /// The clients' times shifted by the clock skew of their session, see `ReportInfo::save_reports`.
+    Corrected,
+}
+
+/// How `SessionFilter::property_value` is compared to the property, as text.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PropertyOp {
+    Exists,
+    Equals,
+    StartsWith,
+    Contains,
+}
+
+impl SessionFilter {
+    pub fn idle_timeout(&self) -> Result<i64, DataError> {
+        match self.idle_timeout {
+            Some(idle_timeout) if idle_timeout < 0 => Err(DataError::InvalidFilter(
+                "idle_timeout can't be negative".to_string(),
+            )),
+            idle_timeout => Ok(idle_timeout.unwrap_or(DEFAULT_IDLE_TIMEOUT_MS)),
+        }
+    }
+
+    /// The name `main.property_matches` knows the operation by. Without an operation, the
+    /// property must equal the value if there is one, or else just exist.
+    fn property_op(&self) -> &'static str {
+        match (self.property_op, &self.property_value) {
+            (Some(PropertyOp::Exists), _) | (None, None) => "exists",
+            (Some(PropertyOp::Equals), _) | (None, Some(_)) => "equals",
+            (Some(PropertyOp::StartsWith), _) => "starts_with",
+            (Some(PropertyOp::Contains), _) => "contains",
+        }
+    }
+
+    /// The value, the empty string matches every value of the property.
+    fn property_value(&self) -> &str {
+        self.property_value.as_deref().unwrap_or("")
+    }
+
+    fn corrected_clock(&self) -> bool {
+        self.clock == Clock::Corrected
+    }
+}
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionSort {
+    #[default]
+    Newest,
+    Longest,
+}
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    pub limit: Option<usize>,
+    /// The `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub sort: SessionSort,
+}
+
+impl PageQuery {
+    fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+#[derive(Serialize)]
+pub struct SessionsPage {
+    pub sessions: Vec<Session>,
+    pub next_cursor: Option<String>,
+}
+
+/// Where a page ends: the sort key and id of its last session.
+struct Cursor {
+    sort_key: i64,
+    session_id: uuid::Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        format!("{}.{}", self.sort_key, self.session_id)
+    }
+
+    fn decode(cursor: &str) -> Option<Cursor> {
+        let mut parts = cursor.splitn(2, '.');
+        let sort_key = parts.next()?.parse().ok()?;
+        let session_id = parts.next()?.parse().ok()?;
+
+        Some(Cursor {
+            sort_key,
+            session_id,
+        })
+    }
+}
+
+/// Statistics of the durations of sessions in millisecs. The duration is the wall-clock time
+/// from the first to the last report, the active time leaves out the idle gaps.
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table = "reports")]
+pub struct DurationStats {
+    pub mean: i64,
+    pub median: i64,
+    pub p90: i64,
+    pub active_mean: i64,
+    pub active_median: i64,
+    pub active_p90: i64,
+    /// The number of visits the sessions are split into by the idle timeout.
+    pub visits: i64,
+}
+
+/// Tag groups need distinct ids, and `OTHER_TAG_GROUP_ID` is reserved.
+pub fn check_tag_groups(tag_groups: &[TagGroup]) -> Result<(), DataError> {
+    let mut ids = HashSet::new();
+    for tag_group in tag_groups {
+        if tag_group.id == OTHER_TAG_GROUP_ID {
+            return Err(DataError::InvalidTagGroups(format!(
+                "tag group id {} is reserved for reports that match no tag group",
+                OTHER_TAG_GROUP_ID
+            )));
+        }
+        if !ids.insert(tag_group.id) {
+            return Err(DataError::InvalidTagGroups(format!(
+                "tag group id {} is used more than once",
+                tag_group.id
+            )));
         }
-        false
     }
+    Ok(())
 }
 
 #[derive(Serialize, Deserialize)]
@@ -56,203 +298,320 @@ impl Responder for Session {
 }
 
 impl Session {
-    pub async fn get_sessions(client: &Client, project_id: i32) -> Result<Vec<Session>, DataError> {
-        let session_ids = Self::get_session_ids(client, project_id).await?;
-        let sessions = futures::future::join_all(
-            session_ids
-                .into_iter()
-                .map(|session_id| Self::get_session(client, session_id)),
+    /// Loads every session of the project with reports in the time range, with one query.
+    /// Sessions are split into their visits if the filter asks for it.
+    pub async fn get_sessions(
+        client: &Client,
+        project_id: i32,
+        filter: &SessionFilter,
+    ) -> Result<Vec<Session>, DataError> {
+        let stmt_str = include_str!("../../sql/get_sessions_of_project.sql");
+        let idle_timeout = filter.idle_timeout()?;
+
+        let sessions = Self::query_sessions(
+            client,
+            stmt_str,
+            &[
+                &project_id,
+                &filter.from,
+                &filter.to,
+                &filter.property,
+                &filter.property_op(),
+                &filter.property_value(),
+                &filter.corrected_clock(),
+            ],
         )
-        .await
-        .into_iter()
-        .collect::<Result<Vec<Session>, DataError>>()?;
+        .await?;
 
-        Ok(sessions)
+        if !filter.split_visits {
+            return Ok(sessions);
+        }
+        Ok(sessions
+            .into_iter()
+            .flat_map(|session| session.into_visits(idle_timeout))
+            .collect())
     }
 
-    pub async fn get_session(
+    /// Loads one page of sessions, in the order asked for. Sessions only include their reports
+    /// in the time range.
+    pub async fn get_sessions_page(
         client: &Client,
-        session_id: uuid::Uuid,
-    ) -> Result<Session, DataError> {
-        let reports_futures = Report::get_reports_of_session(client, session_id)
-            .await?
-            .into_iter()
-            .map(|report| report.into_report_info(client));
-        let reports = futures::future::join_all(reports_futures)
-            .await
-            .into_iter()
-            .collect::<Result<Vec<ReportInfo>, DataError>>()?;
+        project_id: i32,
+        filter: &SessionFilter,
+        page: &PageQuery,
+    ) -> Result<SessionsPage, DataError> {
+        let stmt_str = match page.sort {
+            SessionSort::Newest => include_str!("../../sql/get_sessions_page_newest.sql"),
+            SessionSort::Longest => include_str!("../../sql/get_sessions_page_longest.sql"),
+        };
+
+        let cursor = match &page.cursor {
+            Some(cursor) => Some(Cursor::decode(cursor).ok_or(DataError::InvalidCursor)?),
+            None => None,
+        };
+        let cursor_key = cursor.as_ref().map(|cursor| cursor.sort_key);
+        let cursor_session_id = cursor.as_ref().map(|cursor| cursor.session_id);
+
+        let limit = page.limit();
+        // one more session than asked for tells if there is a next page
+        let fetch_limit = limit as i64 + 1;
+
+        let mut sessions = Self::query_sessions(
+            client,
+            stmt_str,
+            &[
+                &project_id,
+                &filter.from,
+                &filter.to,
+                &cursor_key,
+                &cursor_session_id,
+                &fetch_limit,
+                &filter.property,
+                &filter.property_op(),
+                &filter.property_value(),
+                &filter.corrected_clock(),
+            ],
+        )
+        .await?;
+
+        let next_cursor = if sessions.len() > limit {
+            sessions.truncate(limit);
+            sessions.last().map(|session| {
+                Cursor {
+                    sort_key: session.sort_key(page.sort),
+                    session_id: session.session_id,
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
 
-        Ok(Session {
-            session_id,
-            reports,
+        Ok(SessionsPage {
+            sessions,
+            next_cursor,
         })
     }
 
-    pub async fn get_session_ids(
+    /// Runs a query returning one row per report, sorted by session so that the reports are
+    /// grouped into sessions as they are streamed.
+    async fn query_sessions(
         client: &Client,
-        project_id: i32,
-    ) -> Result<Vec<uuid::Uuid>, DataError> {
-        let stmt_str = include_str!("../../sql/get_session_ids.sql");
-        let stmt = client.prepare(&stmt_str).await?;
+        stmt_str: &str,
+        params: &[&dyn ToSql],
+    ) -> Result<Vec<Session>, DataError> {
+        let stmt = client.prepare(stmt_str).await?;
+
+        let rows = client.query_raw(&stmt, params.iter().copied()).await?;
+        futures::pin_mut!(rows);
+
+        let mut sessions = Vec::<Session>::new();
+        while let Some(row) = rows.try_next().await? {
+            let report_info = ReportInfo {
+                access_key: uuid::Uuid::nil(),
+                session_id: row.get("session_id"),
+                time_ms: row.get("timestamp"),
+                tags: row.get("tags"),
+                properties: row.get("properties"),
+                sent_at: None,
+            };
+
+            match sessions.last_mut() {
+                Some(session) if session.session_id == report_info.session_id => {
+                    session.reports.push(report_info)
+                }
+                _ => sessions.push(Session {
+                    session_id: report_info.session_id,
+                    reports: vec![report_info],
+                }),
+            }
+        }
 
-        Ok(client
-            .query(&stmt, &[&project_id])
-            .await?
-            .iter()
-            .map(|row| row.get::<_, uuid::Uuid>("session_id"))
-            .collect())
+        Ok(sessions)
+    }
+
+    /// The key the session is sorted by, the same as the `sort_key` of the page queries.
+    fn sort_key(&self, sort: SessionSort) -> i64 {
+        match sort {
+            SessionSort::Newest => self.reports.first().map_or(0, |report| report.time_ms),
+            SessionSort::Longest => self.get_session_duration() as i64,
+        }
+    }
+
+    /// Splits the session where the gap between two consecutive reports is longer than
+    /// `idle_timeout` millisecs. The visits keep the session's id.
+    pub fn into_visits(self, idle_timeout: i64) -> Vec<Session> {
+        let session_id = self.session_id;
+        let mut visits = Vec::<Session>::new();
+        let mut last_time_ms = None;
+
+        for report_info in self.reports {
+            let is_idle = last_time_ms
+                .is_none_or(|last_time_ms| report_info.time_ms - last_time_ms > idle_timeout);
+            last_time_ms = Some(report_info.time_ms);
+
+            match visits.last_mut() {
+                Some(visit) if !is_idle => visit.reports.push(report_info),
+                _ => visits.push(Session {
+                    session_id,
+                    reports: vec![report_info],
+                }),
+            }
+        }
+
+        visits
     }
 
-    /// returns the sessions that have at least one of the tags in tag group.
-    pub async fn get_sessions_with_tag_group(
+    /// Counts the sessions that started in the filter's range, like the rollups do.
+    pub async fn get_sessions_count(
         client: &Client,
         project_id: i32,
-        tag_group: &TagGroup,
-    ) -> Result<Vec<Session>, DataError> {
-        Ok(Self::get_sessions(client, project_id)
-            .await?
-            .into_iter()
-            .filter(|session| session.contains_tag_group(tag_group))
-            .collect::<Vec<Session>>())
-    }
+        filter: &SessionFilter,
+    ) -> Result<i64, DataError> {
+        let stmt_str = include_str!("../../sql/get_sessions_count.sql");
+        let stmt = client.prepare(stmt_str).await?;
 
-    pub async fn get_sessions_count(client: &Client, project_id: i32) -> Result<usize, DataError> {
-        // FIXME: no need to get all the sessions, use a SQL query to only get the len
-        let sessions = Self::get_sessions(client, project_id).await?;
-        Ok(sessions.len())
+        Ok(client
+            .query_one(
+                &stmt,
+                &[
+                    &project_id,
+                    &filter.from,
+                    &filter.to,
+                    &filter.property,
+                    &filter.property_op(),
+                    &filter.property_value(),
+                    &filter.corrected_clock(),
+                ],
+            )
+            .await?
+            .get("count"))
     }
 
     pub async fn get_percentages(
         client: &Client,
         project_id: i32,
+        filter: &SessionFilter,
         tag_groups: &[TagGroup],
     ) -> Result<Vec<Percentage>, DataError> {
-        let sessions_lists = futures::future::join_all(
-            tag_groups
-                .iter()
-                .map(|tag_group| Self::get_sessions_with_tag_group(client, project_id, tag_group)),
-        )
-        .await
-        .into_iter()
-        .collect::<Result<Vec<Vec<Session>>, DataError>>()?;
-
-        let session_counts = sessions_lists
-            .iter()
-            .map(|list| list.len())
-            .collect::<Vec<usize>>();
-
-        let total_count = Self::get_sessions_count(client, project_id).await?;
+        let sessions = Self::get_sessions(client, project_id, filter).await?;
 
-        if total_count == 0 {
+        if sessions.is_empty() {
             return Err(DataError::NoSessionFound);
         }
 
-        Ok(session_counts
-            .into_iter()
-            .map(|count| ((((count as f64 / total_count as f64) as f64) * 100.0) as u32).into())
+        Ok(tag_groups
+            .iter()
+            .map(|tag_group| {
+                let count = sessions
+                    .iter()
+                    .filter(|session| session.contains_tag_group(tag_group))
+                    .count();
+                (((count as f64 / sessions.len() as f64) * 100.0) as u32).into()
+            })
             .collect::<Vec<Percentage>>())
     }
 
     fn contains_tag_group(&self, tag_group: &TagGroup) -> bool {
         self.reports
             .iter()
-            .any(|report| tag_group.contains_any(&report.tags))
+            .any(|report| tag_group.matches(&report.tags))
     }
 
-    /// maps each report to the first tag-group that has any of that report's tags
+    /// maps each report to the id of the first tag-group that has any of that report's tags,
+    /// or to `OTHER_TAG_GROUP_ID` if there is none
     fn group_ids(&self, tag_groups: &[TagGroup]) -> Vec<i32> {
         self.reports
             .iter()
             .map(|report_info| {
                 tag_groups
                     .iter()
-                    .find(|&tag_group| tag_group.contains_any(&report_info.tags))
-                    .map_or(0, |tag_group| tag_group.id)
+                    .find(|&tag_group| tag_group.matches(&report_info.tags))
+                    .map_or(OTHER_TAG_GROUP_ID, |tag_group| tag_group.id)
             })
             .collect()
     }
 
-    fn group_by_ids(&self, group_ids: &[i32]) -> Vec<Vec<ReportInfo>> {
-        let mut current_id = -1;
-        let mut current_group = vec![];
-        let mut result = vec![];
-        for (idx, id) in group_ids.iter().enumerate() {
-            if *id != current_id && !current_group.is_empty() {
-                result.push(current_group.clone());
-                current_group.clear();
+    /// splits the reports into runs of consecutive reports with the same group id
+    fn group_by_ids(&self, group_ids: &[i32]) -> Vec<(i32, &[ReportInfo])> {
+        let mut runs = vec![];
+        let mut run_start = 0;
+        for idx in 1..=group_ids.len() {
+            if idx == group_ids.len() || group_ids[idx] != group_ids[run_start] {
+                runs.push((group_ids[run_start], &self.reports[run_start..idx]));
+                run_start = idx;
             }
-            current_group.push(self.reports[idx].clone());
-            current_id = *id;
-        }
-        if !current_group.is_empty() {
-            result.push(current_group);
         }
-        result
+        runs
     }
 
+    /// The wall-clock time from the first to the last report, the reports are sorted by time.
     fn get_session_duration(&self) -> u64 {
-        if self.reports.is_empty() {
-            0
-        } else {
-            (self.reports[1].time_ms - self.reports[0].time_ms) as u64
+        match (self.reports.first(), self.reports.last()) {
+            (Some(first), Some(last)) => (last.time_ms - first.time_ms) as u64,
+            _ => 0,
         }
     }
 
-    pub async fn get_average_session_duration(
+    /// The duration statistics of the sessions that started in the filter's range, with all
+    /// their reports, like the rollups count them.
+    pub async fn get_duration_stats(
         client: &Client,
         project_id: i32,
-    ) -> Result<Duration, DataError> {
-        let sessions = Self::get_sessions(client, project_id).await?;
-
-        if sessions.is_empty() {
-            return Ok(Duration::from_millis(0));
-        }
-
-        let avg_duration_ms = sessions
-            .iter()
-            .map(|s| s.get_session_duration())
-            .sum::<u64>()
-            / sessions.len() as u64;
-        Ok(Duration::from_millis(avg_duration_ms))
+        filter: &SessionFilter,
+    ) -> Result<DurationStats, DataError> {
+        let stmt_str = include_str!("../../sql/get_session_duration_stats.sql");
+        let stmt = client.prepare(stmt_str).await?;
+        let idle_timeout = filter.idle_timeout()?;
+
+        let row = client
+            .query_one(
+                &stmt,
+                &[
+                    &project_id,
+                    &filter.from,
+                    &filter.to,
+                    &idle_timeout,
+                    &filter.property,
+                    &filter.property_op(),
+                    &filter.property_value(),
+                    &filter.corrected_clock(),
+                ],
+            )
+            .await?;
+        Ok(DurationStats::from_row_ref(&row)?)
     }
 
+    /// Turns each run of consecutive reports with the same tag group into a step. A step lasts
+    /// from its first report to the first report of the next step, the last one until its own
+    /// last report. The tag groups must pass `check_tag_groups`.
     pub fn into_grouped_session(self, tag_groups: &[TagGroup]) -> GroupedSession {
-        let tag_group_ids = self.group_ids(tag_groups);
-
-        println!("{:#?} tag_groups: ", tag_group_ids);
-        println!("---");
-
-        let group_reports = self.group_by_ids(&tag_group_ids);
-
-        println!("{:#?} group_reports: ", group_reports);
-        println!("---");
-
-        let timestamps = group_reports
+        let tag_groups_by_id = tag_groups
             .iter()
-            .map(|report_group| {
-                report_group
-                    .last()
-                    .map_or(0, |last_report| last_report.time_ms)
-            })
-            .collect::<Vec<i64>>();
-
-        let mut last_timestamp = 0;
-        let mut durations = Vec::<i64>::new();
-        for timestamp in timestamps {
-            durations.push(timestamp - last_timestamp);
-            last_timestamp = timestamp;
-        }
+            .map(|tag_group| (tag_group.id, tag_group))
+            .collect::<HashMap<i32, &TagGroup>>();
 
-        println!("{:#?} group_reports", durations);
+        let group_ids = self.group_ids(tag_groups);
+        let runs = self.group_by_ids(&group_ids);
 
-        let steps = group_reports[1..]
+        let steps = runs
             .iter()
             .enumerate()
-            .map(|(idx, _report_group)| Step {
-                step_number: idx,
-                tag_group: tag_groups[tag_group_ids[idx + 1] as usize].clone(),
-                duration: Duration::from_millis(durations[idx + 1].abs() as u64),
+            .map(|(idx, (tag_group_id, reports))| {
+                let started_at = reports.first().map_or(0, |report| report.time_ms);
+                let ended_at = match runs.get(idx + 1) {
+                    Some((_, next_reports)) => next_reports.first(),
+                    None => reports.last(),
+                }
+                .map_or(started_at, |report| report.time_ms);
+
+                Step {
+                    step_number: idx,
+                    tag_group: tag_groups_by_id
+                        .get(tag_group_id)
+                        .map_or_else(TagGroup::other, |&tag_group| tag_group.clone()),
+                    duration: Duration::from_millis((ended_at - started_at).max(0) as u64),
+                }
             })
             .collect();
 
@@ -288,7 +647,7 @@ pub fn grouped_sessions_to_session_analysis(
 }
 
 pub fn get_step_analysis(grouped_sessions: &[GroupedSession], step_number: usize) -> StepAnalysis {
-    let mut tag_group_counts = HashMap::<TagGroup, u32>::new();
+    let mut tag_group_counts = HashMap::<i32, (&TagGroup, u32)>::new();
     let mut duration_sum = 0;
 
     let mut step_counts = 0;
@@ -303,20 +662,199 @@ pub fn get_step_analysis(grouped_sessions: &[GroupedSession], step_number: usize
         let step = &gs.steps[step_number];
         duration_sum += step.duration.as_millis();
 
-        let prev_count = *tag_group_counts.entry(step.tag_group.clone()).or_insert(0);
-        tag_group_counts.insert(step.tag_group.clone(), prev_count + 1);
+        tag_group_counts
+            .entry(step.tag_group.id)
+            .or_insert((&step.tag_group, 0))
+            .1 += 1;
     });
 
     // sort the tag-groups based on their count
-    let mut tag_group_counts: Vec<(TagGroup, u32)> = tag_group_counts
-        .iter()
-        .map(|(tg, count)| (tg.clone(), *count))
-        .collect();
-    tag_group_counts.sort_by(|l, r| l.1.cmp(&r.1));
+    let mut tag_group_counts: Vec<(&TagGroup, u32)> = tag_group_counts.into_values().collect();
+    // most common first
+    tag_group_counts.sort_by(|l, r| r.1.cmp(&l.1).then(l.0.id.cmp(&r.0.id)));
 
     StepAnalysis {
         step_number,
-        average_duration: (duration_sum / step_counts) as i64,
-        tag_groups_sorted: tag_group_counts.iter().map(|(tg, _)| tg.clone()).collect(),
+        average_duration: duration_sum.checked_div(step_counts).unwrap_or(0) as i64,
+        tag_groups_sorted: tag_group_counts
+            .iter()
+            .map(|(tg, _)| (*tg).clone())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn tag_group(id: i32, tags_names: &[&str]) -> TagGroup {
+        let tags_names = tags_names.iter().map(|name| name.to_string()).collect();
+        TagGroup::new(id, tags_names, None).unwrap()
+    }
+
+    fn session(reports: &[(i64, &[&str])]) -> Session {
+        let session_id = uuid::Uuid::nil();
+        Session {
+            session_id,
+            reports: reports
+                .iter()
+                .map(|&(time_ms, tags)| ReportInfo {
+                    access_key: uuid::Uuid::nil(),
+                    session_id,
+                    time_ms,
+                    tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                    properties: serde_json::json!({}),
+                    sent_at: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn step_ids(grouped_session: &GroupedSession) -> Vec<(i32, u64)> {
+        grouped_session
+            .steps
+            .iter()
+            .map(|step| (step.tag_group.id, step.duration.as_millis() as u64))
+            .collect()
+    }
+
+    /// Sessions of 0 to 20 reports, sorted by time, with 0 to 2 of the tags `a` to `d` each.
+    fn random_sessions(count: usize) -> Vec<Session> {
+        let mut rng = StdRng::seed_from_u64(7);
+        let tags = ["a", "b", "c", "d"];
+        (0..count)
+            .map(|_| {
+                let mut time_ms = rng.gen_range(0, 1_000_000);
+                let reports = (0..rng.gen_range(0, 21))
+                    .map(|_| {
+                        time_ms += rng.gen_range(0, 10_000);
+                        let report_tags = (0..rng.gen_range(0, 3))
+                            .map(|_| tags[rng.gen_range(0, tags.len())])
+                            .collect::<Vec<&str>>();
+                        (time_ms, report_tags)
+                    })
+                    .collect::<Vec<(i64, Vec<&str>)>>();
+                session(
+                    &reports
+                        .iter()
+                        .map(|(time_ms, report_tags)| (*time_ms, report_tags.as_slice()))
+                        .collect::<Vec<(i64, &[&str])>>(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn duplicate_tag_group_ids_are_rejected() {
+        let tag_groups = vec![tag_group(1, &["a"]), tag_group(1, &["b"])];
+
+        assert!(check_tag_groups(&tag_groups).is_err());
+    }
+
+    #[test]
+    fn the_other_tag_group_id_is_reserved() {
+        let tag_groups = vec![tag_group(OTHER_TAG_GROUP_ID, &["a"])];
+
+        assert!(check_tag_groups(&tag_groups).is_err());
+    }
+
+    #[test]
+    fn overlapping_tag_groups_take_the_first_match() {
+        let tag_groups = vec![tag_group(1, &["a", "b"]), tag_group(2, &["b", "c"])];
+        assert!(check_tag_groups(&tag_groups).is_ok());
+
+        let grouped_session = session(&[(0, &["b"]), (10, &["c"]), (30, &["a", "c"])])
+            .into_grouped_session(&tag_groups);
+
+        assert_eq!(step_ids(&grouped_session), vec![(1, 10), (2, 20), (1, 0)]);
+    }
+
+    #[test]
+    fn consecutive_reports_of_a_group_make_one_step() {
+        let tag_groups = vec![tag_group(1, &["a"])];
+
+        let grouped_session = session(&[(0, &["a"]), (5, &["a"]), (20, &["x"]), (50, &["y"])])
+            .into_grouped_session(&tag_groups);
+
+        assert_eq!(
+            step_ids(&grouped_session),
+            vec![(1, 20), (OTHER_TAG_GROUP_ID, 30)]
+        );
+    }
+
+    #[test]
+    fn step_durations_add_up_to_the_session_duration() {
+        let tag_groups = vec![tag_group(1, &["a"]), tag_group(2, &["b", "c"])];
+
+        for session in random_sessions(500) {
+            let session_duration = session.get_session_duration();
+            let grouped_session = session.into_grouped_session(&tag_groups);
+
+            let steps_duration = grouped_session
+                .steps
+                .iter()
+                .map(|step| step.duration.as_millis() as u64)
+                .sum::<u64>();
+            assert_eq!(steps_duration, session_duration);
+        }
+    }
+
+    #[test]
+    fn sessions_of_zero_or_one_report_have_at_most_one_step() {
+        let tag_groups = vec![tag_group(1, &["a"])];
+
+        let grouped_session = session(&[]).into_grouped_session(&tag_groups);
+        assert!(grouped_session.steps.is_empty());
+        for tags in &[&["a"][..], &["b"], &[]] {
+            let grouped_session = session(&[(100, tags)]).into_grouped_session(&tag_groups);
+            assert_eq!(grouped_session.steps.len(), 1);
+            assert_eq!(grouped_session.steps[0].duration, Duration::from_millis(0));
+        }
+    }
+
+    #[test]
+    fn every_report_is_in_exactly_one_step() {
+        let tag_groups = vec![tag_group(1, &["a"]), tag_group(2, &["b", "c"])];
+
+        for session in random_sessions(500) {
+            let group_ids = session.group_ids(&tag_groups);
+            let runs = session.group_by_ids(&group_ids);
+
+            let run_reports = runs
+                .iter()
+                .flat_map(|(_, reports)| reports.iter())
+                .map(|report| report as *const ReportInfo)
+                .collect::<Vec<*const ReportInfo>>();
+            let reports = session
+                .reports
+                .iter()
+                .map(|report| report as *const ReportInfo)
+                .collect::<Vec<*const ReportInfo>>();
+            assert_eq!(run_reports, reports);
+
+            let mut report_group_ids = group_ids.iter();
+            for (tag_group_id, reports) in &runs {
+                assert!(!reports.is_empty());
+                for _ in reports.iter() {
+                    assert_eq!(report_group_ids.next(), Some(tag_group_id));
+                }
+            }
+            for pair in runs.windows(2) {
+                assert_ne!(pair[0].0, pair[1].0);
+            }
+
+            let run_ids = runs
+                .iter()
+                .map(|(tag_group_id, _)| *tag_group_id)
+                .collect::<Vec<i32>>();
+            let steps = session.into_grouped_session(&tag_groups).steps;
+            let step_ids = steps
+                .iter()
+                .map(|step| step.tag_group.id)
+                .collect::<Vec<i32>>();
+            assert_eq!(step_ids, run_ids);
+        }
     }
 }
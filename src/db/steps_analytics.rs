@@ -1,12 +1,19 @@
+use crate::db::percentage::Percentage;
 use crate::db::sessions::{GroupedSession, Step, TagGroup};
+use serde::Serialize;
 use std::collections::HashMap;
-use std::time::Duration;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize)]
 pub struct TagGroupFrequency {
-    tag_group: TagGroup,
-    count: u32,
-    average_duration: Duration,
+    pub tag_group: TagGroup,
+    pub count: u32,
+    /// The share of the sessions reaching the step that had this tag group at it.
+    pub share: Percentage,
+    /// In millisecs.
+    pub average_duration: i64,
+    /// In millisecs, divided by `count` once every step is merged so that no rounding adds up.
+    #[serde(skip)]
+    duration_sum: u128,
 }
 
 impl TagGroupFrequency {
@@ -14,39 +21,135 @@ impl TagGroupFrequency {
         TagGroupFrequency {
             tag_group: step.tag_group.clone(),
             count: 1,
-            average_duration: step.duration.clone(),
+            share: 0u32.into(),
+            average_duration: 0,
+            duration_sum: step.duration.as_millis(),
         }
     }
 
     pub fn merge(&mut self, step: &Step) {
-        self.average_duration = Duration::from_millis(
-            ((self.average_duration.as_millis() * self.count as u128 + step.duration.as_millis())
-                / (self.count + 1) as u128) as u64,
-        );
+        self.duration_sum += step.duration.as_millis();
         self.count += 1;
     }
 }
 
-pub async fn tag_group_frequency_at_step(
-    grouped_sessions: Vec<GroupedSession>,
+/// Counts the tag groups of the sessions at `step_number`, most common first. Sessions with
+/// fewer steps are left out.
+pub fn tag_group_frequency_at_step(
+    grouped_sessions: &[GroupedSession],
     step_number: usize,
-) {
-    let freqs = HashMap::<usize, TagGroupFrequency>::new();
-
-    let steps = grouped_sessions
-        .into_iter()
-        .map(|session| session.steps[step_number].clone())
-        .collect::<Vec<Step>>();
-
-    for step in steps {
-        let prev_freq = freqs.get(&step.step_number);
-        let new_freq = match prev_freq {
-            Some(fr) => {
-                let mut a = fr.clone();
-                a.merge(&step);
-                a
+) -> Vec<TagGroupFrequency> {
+    let mut freqs = HashMap::<i32, TagGroupFrequency>::new();
+
+    for step in grouped_sessions
+        .iter()
+        .filter_map(|session| session.steps.get(step_number))
+    {
+        match freqs.get_mut(&step.tag_group.id) {
+            Some(freq) => freq.merge(step),
+            None => {
+                freqs.insert(step.tag_group.id, TagGroupFrequency::new(step));
             }
-            None => TagGroupFrequency::new(&step),
-        };
+        }
+    }
+
+    let step_count = freqs.values().map(|freq| freq.count).sum::<u32>();
+
+    let mut freqs = freqs
+        .into_values()
+        .map(|mut freq| {
+            freq.share = (freq.count * 100 / step_count).into();
+            freq.average_duration = (freq.duration_sum / freq.count as u128) as i64;
+            freq
+        })
+        .collect::<Vec<TagGroupFrequency>>();
+    freqs.sort_by(|l, r| {
+        r.count
+            .cmp(&l.count)
+            .then(l.tag_group.id.cmp(&r.tag_group.id))
+    });
+
+    freqs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn tag_group(id: i32) -> TagGroup {
+        TagGroup::new(id, vec![format!("tag-{}", id)], None).unwrap()
+    }
+
+    fn session(steps: &[(i32, u64)]) -> GroupedSession {
+        GroupedSession {
+            steps: steps
+                .iter()
+                .enumerate()
+                .map(|(step_number, &(tag_group_id, duration_ms))| Step {
+                    step_number,
+                    tag_group: tag_group(tag_group_id),
+                    duration: Duration::from_millis(duration_ms),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn no_sessions_have_no_frequencies() {
+        assert!(tag_group_frequency_at_step(&[], 0).is_empty());
+    }
+
+    #[test]
+    fn sessions_shorter_than_the_step_are_left_out() {
+        let sessions = vec![session(&[(1, 100)]), session(&[(1, 100), (2, 300)])];
+
+        assert!(tag_group_frequency_at_step(&sessions, 5).is_empty());
+
+        let freqs = tag_group_frequency_at_step(&sessions, 1);
+        assert_eq!(freqs.len(), 1);
+        assert_eq!(freqs[0].tag_group.id, 2);
+        assert_eq!(freqs[0].count, 1);
+        assert_eq!(freqs[0].share, Percentage::from(100u32));
+        assert_eq!(freqs[0].average_duration, 300);
+    }
+
+    #[test]
+    fn ties_are_sorted_by_tag_group_id() {
+        let sessions = vec![
+            session(&[(3, 100)]),
+            session(&[(2, 200)]),
+            session(&[(3, 300)]),
+            session(&[(2, 400)]),
+            session(&[(1, 500)]),
+        ];
+
+        let freqs = tag_group_frequency_at_step(&sessions, 0);
+        let ids = freqs
+            .iter()
+            .map(|freq| freq.tag_group.id)
+            .collect::<Vec<i32>>();
+        assert_eq!(ids, vec![2, 3, 1]);
+        assert_eq!(freqs[0].share, Percentage::from(40u32));
+        assert_eq!(freqs[0].average_duration, 300);
+        assert_eq!(freqs[1].average_duration, 200);
+    }
+
+    #[test]
+    fn average_duration_does_not_drift() {
+        let sessions = (0..1000)
+            .map(|duration_ms| session(&[(1, duration_ms)]))
+            .collect::<Vec<GroupedSession>>();
+
+        let freqs = tag_group_frequency_at_step(&sessions, 0);
+        assert_eq!(freqs[0].average_duration, 499);
+    }
+
+    #[test]
+    fn average_duration_is_serialized_in_millisecs() {
+        let freqs = tag_group_frequency_at_step(&[session(&[(1, 1500)])], 0);
+
+        let serialized = serde_json::to_value(&freqs[0]).unwrap();
+        assert_eq!(serialized["average_duration"], 1500);
     }
 }
@@ -0,0 +1,237 @@
+use crate::db::report_validation::MAX_TAG_LEN;
+use crate::db::sessions::TagGroup;
+use crate::db::tag_rules::TagRule;
+use crate::dberror::DataError;
+use deadpool_postgres::Client;
+use postgres_types::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+use tokio_postgres::error::SqlState;
+
+/// `main.tag_groups.name` is a `varchar(50)`.
+const MAX_NAME_LEN: usize = 50;
+
+/// A tag group saved for a project, so that everyone analyses sessions with the same groups.
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table = "tag_groups")]
+pub struct SavedTagGroup {
+    pub tag_group_id: i32,
+    pub name: String,
+    pub description: String,
+    pub tags_names: Vec<String>,
+    pub rule: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+pub struct TagGroupInfo {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub tags_names: Vec<String>,
+    /// Overrides `tags_names` when set.
+    #[serde(default)]
+    pub rule: Option<TagRule>,
+}
+
+impl TagGroupInfo {
+    fn validate(&self) -> Result<(), DataError> {
+        if self.name.is_empty() || self.name.chars().count() > MAX_NAME_LEN {
+            return Err(DataError::InvalidTagGroups(format!(
+                "the name must have 1 to {} characters",
+                MAX_NAME_LEN
+            )));
+        }
+        if let Some(tag_name) = self
+            .tags_names
+            .iter()
+            .find(|tag_name| tag_name.chars().count() > MAX_TAG_LEN)
+        {
+            return Err(DataError::InvalidTagGroups(format!(
+                "tag `{}` is longer than {} characters",
+                tag_name, MAX_TAG_LEN
+            )));
+        }
+        if let Some(rule) = &self.rule {
+            rule.compile()?;
+        }
+        Ok(())
+    }
+}
+
+/// How analytics requests refer to a tag group: by the id of a saved one, or by the whole
+/// definition.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum TagGroupRef {
+    Saved(i32),
+    AdHoc(TagGroup),
+}
+
+impl TryFrom<SavedTagGroup> for TagGroup {
+    type Error = DataError;
+
+    fn try_from(saved: SavedTagGroup) -> Result<Self, Self::Error> {
+        let tag_group_id = saved.tag_group_id;
+        let rule = saved
+            .rule
+            .map(serde_json::from_value::<TagRule>)
+            .transpose()
+            .map_err(|err| {
+                DataError::InvalidTagGroups(format!(
+                    "saved tag group {} has an invalid rule: {}",
+                    tag_group_id, err
+                ))
+            })?;
+
+        TagGroup::new(tag_group_id, saved.tags_names, rule)
+    }
+}
+
+fn name_taken(name: &str) -> DataError {
+    DataError::InvalidTagGroups(format!("a tag group named `{}` already exists", name))
+}
+
+impl SavedTagGroup {
+    pub async fn get_tag_groups(
+        client: &Client,
+        project_id: i32,
+    ) -> Result<Vec<SavedTagGroup>, DataError> {
+        let stmt_str = include_str!("../../sql/get_tag_groups_of_project.sql");
+        let stmt = client.prepare(stmt_str).await?;
+
+        Ok(client
+            .query(&stmt, &[&project_id])
+            .await?
+            .iter()
+            .map(|row| SavedTagGroup::from_row_ref(row).unwrap())
+            .collect::<Vec<SavedTagGroup>>())
+    }
+
+    pub async fn save_tag_group(
+        client: &Client,
+        project_id: i32,
+        tag_group_info: &TagGroupInfo,
+    ) -> Result<SavedTagGroup, DataError> {
+        tag_group_info.validate()?;
+
+        let stmt_str = include_str!("../../sql/insert_tag_group.sql");
+        let stmt = client.prepare(stmt_str).await?;
+
+        client
+            .query(
+                &stmt,
+                &[
+                    &project_id,
+                    &tag_group_info.name,
+                    &tag_group_info.description,
+                    &tag_group_info.tags_names,
+                    &tag_group_info.rule.as_ref().map(Json),
+                ],
+            )
+            .await?
+            .iter()
+            .map(|row| SavedTagGroup::from_row_ref(row).unwrap())
+            .collect::<Vec<SavedTagGroup>>()
+            .pop()
+            .ok_or_else(|| name_taken(&tag_group_info.name))
+    }
+
+    pub async fn update_tag_group(
+        client: &Client,
+        project_id: i32,
+        tag_group_id: i32,
+        tag_group_info: &TagGroupInfo,
+    ) -> Result<SavedTagGroup, DataError> {
+        tag_group_info.validate()?;
+
+        let stmt_str = include_str!("../../sql/update_tag_group.sql");
+        let stmt = client.prepare(stmt_str).await?;
+
+        client
+            .query(
+                &stmt,
+                &[
+                    &project_id,
+                    &tag_group_id,
+                    &tag_group_info.name,
+                    &tag_group_info.description,
+                    &tag_group_info.tags_names,
+                    &tag_group_info.rule.as_ref().map(Json),
+                ],
+            )
+            .await
+            .map_err(|err| {
+                if err.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                    name_taken(&tag_group_info.name)
+                } else {
+                    err.into()
+                }
+            })?
+            .iter()
+            .map(|row| SavedTagGroup::from_row_ref(row).unwrap())
+            .collect::<Vec<SavedTagGroup>>()
+            .pop()
+            .ok_or(DataError::NotFound)
+    }
+
+    pub async fn delete_tag_group(
+        client: &Client,
+        project_id: i32,
+        tag_group_id: i32,
+    ) -> Result<(), DataError> {
+        let stmt_str = include_str!("../../sql/delete_tag_group.sql");
+        let stmt = client.prepare(stmt_str).await?;
+
+        let deleted = client.query(&stmt, &[&project_id, &tag_group_id]).await?;
+        if deleted.is_empty() {
+            Err(DataError::NotFound)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Turns the references into tag groups, in the same order. Saved groups must belong to the
+/// project.
+pub async fn resolve_tag_groups(
+    client: &Client,
+    project_id: i32,
+    tag_group_refs: Vec<TagGroupRef>,
+) -> Result<Vec<TagGroup>, DataError> {
+    let saved_ids = tag_group_refs
+        .iter()
+        .filter_map(|tag_group_ref| match tag_group_ref {
+            TagGroupRef::Saved(id) => Some(*id),
+            TagGroupRef::AdHoc(_) => None,
+        })
+        .collect::<Vec<i32>>();
+
+    let saved_tag_groups = if saved_ids.is_empty() {
+        HashMap::new()
+    } else {
+        let stmt_str = include_str!("../../sql/get_tag_groups_by_ids.sql");
+        let stmt = client.prepare(stmt_str).await?;
+
+        client
+            .query(&stmt, &[&project_id, &saved_ids])
+            .await?
+            .iter()
+            .map(|row| SavedTagGroup::from_row_ref(row).unwrap())
+            .map(|saved| Ok((saved.tag_group_id, TagGroup::try_from(saved)?)))
+            .collect::<Result<HashMap<i32, TagGroup>, DataError>>()?
+    };
+
+    tag_group_refs
+        .into_iter()
+        .map(|tag_group_ref| match tag_group_ref {
+            TagGroupRef::AdHoc(tag_group) => Ok(tag_group),
+            TagGroupRef::Saved(id) => saved_tag_groups.get(&id).cloned().ok_or_else(|| {
+                DataError::InvalidTagGroups(format!("there is no saved tag group {}", id))
+            }),
+        })
+        .collect()
+}
@@ -0,0 +1,209 @@
+use crate::dberror::DataError;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Regexes are compiled per request, this keeps a single pattern from taking too much memory.
+const MAX_REGEX_SIZE: usize = 1 << 20;
+
+/// Decides which reports belong to a tag group, by looking at their tags.
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq, Hash)]
+#[serde(tag = "match", rename_all = "snake_case")]
+pub enum TagRule {
+    /// The report has at least one of the tags.
+    Any {
+        tags: Vec<String>,
+    },
+    /// The report has all of the tags.
+    All {
+        tags: Vec<String>,
+    },
+    /// The report has none of the tags.
+    None {
+        tags: Vec<String>,
+    },
+    /// The report has a tag starting with the prefix, a trailing `*` is ignored so `checkout/*`
+    /// matches `checkout/cart` and `checkout/payment`.
+    Prefix {
+        prefix: String,
+    },
+    /// The report has a tag matching the whole pattern.
+    Regex {
+        pattern: String,
+    },
+    And {
+        rules: Vec<TagRule>,
+    },
+    Or {
+        rules: Vec<TagRule>,
+    },
+    Not {
+        rule: Box<TagRule>,
+    },
+}
+
+/// A `TagRule` ready to be evaluated, with its tags in sets and its patterns compiled.
+#[derive(Clone)]
+pub enum TagMatcher {
+    Any(HashSet<String>),
+    All(HashSet<String>),
+    None(HashSet<String>),
+    Prefix(String),
+    Regex(Regex),
+    And(Vec<TagMatcher>),
+    Or(Vec<TagMatcher>),
+    Not(Box<TagMatcher>),
+}
+
+impl TagRule {
+    pub fn compile(&self) -> Result<TagMatcher, DataError> {
+        Ok(match self {
+            TagRule::Any { tags } => TagMatcher::Any(tags.iter().cloned().collect()),
+            TagRule::All { tags } => TagMatcher::All(tags.iter().cloned().collect()),
+            TagRule::None { tags } => TagMatcher::None(tags.iter().cloned().collect()),
+            TagRule::Prefix { prefix } => {
+                TagMatcher::Prefix(prefix.trim_end_matches('*').to_string())
+            }
+            TagRule::Regex { pattern } => TagMatcher::Regex(
+                RegexBuilder::new(&format!("^(?:{})$", pattern))
+                    .size_limit(MAX_REGEX_SIZE)
+                    .build()
+                    .map_err(|err| {
+                        DataError::InvalidTagGroups(format!(
+                            "invalid pattern `{}`: {}",
+                            pattern, err
+                        ))
+                    })?,
+            ),
+            TagRule::And { rules } => TagMatcher::And(
+                rules
+                    .iter()
+                    .map(TagRule::compile)
+                    .collect::<Result<Vec<TagMatcher>, _>>()?,
+            ),
+            TagRule::Or { rules } => TagMatcher::Or(
+                rules
+                    .iter()
+                    .map(TagRule::compile)
+                    .collect::<Result<Vec<TagMatcher>, _>>()?,
+            ),
+            TagRule::Not { rule } => TagMatcher::Not(Box::new(rule.compile()?)),
+        })
+    }
+}
+
+impl TagMatcher {
+    /// Whether a report with `tags` matches, `And` of no rules always matches and `Or` of no
+    /// rules never does.
+    pub fn matches(&self, tags: &[String]) -> bool {
+        match self {
+            TagMatcher::Any(set) => tags.iter().any(|tag| set.contains(tag)),
+            TagMatcher::All(set) => {
+                let tags = tags.iter().collect::<HashSet<&String>>();
+                set.iter().all(|tag| tags.contains(tag))
+            }
+            TagMatcher::None(set) => !tags.iter().any(|tag| set.contains(tag)),
+            TagMatcher::Prefix(prefix) => tags.iter().any(|tag| tag.starts_with(prefix.as_str())),
+            TagMatcher::Regex(regex) => tags.iter().any(|tag| regex.is_match(tag)),
+            TagMatcher::And(matchers) => matchers.iter().all(|matcher| matcher.matches(tags)),
+            TagMatcher::Or(matchers) => matchers.iter().any(|matcher| matcher.matches(tags)),
+            TagMatcher::Not(matcher) => !matcher.matches(tags),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn matches(rule: serde_json::Value, report_tags: &[&str]) -> bool {
+        serde_json::from_value::<TagRule>(rule)
+            .unwrap()
+            .compile()
+            .unwrap()
+            .matches(&tags(report_tags))
+    }
+
+    #[test]
+    fn any_needs_one_of_the_tags() {
+        let rule = serde_json::json!({ "match": "any", "tags": ["a", "b"] });
+        assert!(matches(rule.clone(), &["b", "c"]));
+        assert!(!matches(rule.clone(), &["c"]));
+        assert!(!matches(rule, &[]));
+    }
+
+    #[test]
+    fn all_needs_every_tag() {
+        let rule = serde_json::json!({ "match": "all", "tags": ["a", "b"] });
+        assert!(matches(rule.clone(), &["b", "c", "a"]));
+        assert!(!matches(rule, &["a", "c"]));
+    }
+
+    #[test]
+    fn none_rejects_any_of_the_tags() {
+        let rule = serde_json::json!({ "match": "none", "tags": ["a", "b"] });
+        assert!(matches(rule.clone(), &["c"]));
+        assert!(matches(rule.clone(), &[]));
+        assert!(!matches(rule, &["c", "b"]));
+    }
+
+    #[test]
+    fn prefix_ignores_a_trailing_star() {
+        for prefix in &["checkout/", "checkout/*"] {
+            let rule = serde_json::json!({ "match": "prefix", "prefix": prefix });
+            assert!(matches(rule.clone(), &["checkout/cart"]));
+            assert!(matches(rule.clone(), &["home", "checkout/payment"]));
+            assert!(!matches(rule, &["checkout"]));
+        }
+    }
+
+    #[test]
+    fn regex_matches_whole_tags() {
+        let rule = serde_json::json!({ "match": "regex", "pattern": "page-\\d+" });
+        assert!(matches(rule.clone(), &["page-12"]));
+        assert!(!matches(rule.clone(), &["page-12/edit"]));
+        assert!(!matches(rule.clone(), &["my-page-12"]));
+
+        let rule = serde_json::json!({ "match": "regex", "pattern": "a|b" });
+        assert!(matches(rule.clone(), &["a"]));
+        assert!(!matches(rule, &["ab"]));
+    }
+
+    #[test]
+    fn empty_and_matches_and_empty_or_does_not() {
+        let and = serde_json::json!({ "match": "and", "rules": [] });
+        let or = serde_json::json!({ "match": "or", "rules": [] });
+        assert!(matches(and, &["a"]));
+        assert!(!matches(or, &["a"]));
+    }
+
+    #[test]
+    fn combinators_nest() {
+        let rule = serde_json::json!({
+            "match": "and",
+            "rules": [
+                { "match": "prefix", "prefix": "checkout/" },
+                { "match": "not", "rule": { "match": "any", "tags": ["checkout/cancel"] } },
+            ],
+        });
+        assert!(matches(rule.clone(), &["checkout/cart"]));
+        assert!(!matches(rule, &["checkout/cart", "checkout/cancel"]));
+    }
+
+    #[test]
+    fn invalid_pattern_is_rejected() {
+        let rule = TagRule::Or {
+            rules: vec![TagRule::Regex {
+                pattern: "page-(".to_string(),
+            }],
+        };
+        match rule.compile() {
+            Err(DataError::InvalidTagGroups(message)) => assert!(message.contains("page-(")),
+            _ => panic!("the pattern should be rejected"),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::db::report_validation::MAX_TAG_LEN;
+use crate::db::rollups;
+use crate::dberror::DataError;
+use deadpool_postgres::Client;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+
+/// How a project's reports use a tag, under the name the project shows it with. Sessions count
+/// with all their reports on the day they started, like in the rollups. Times are millisecs by
+/// the client's clock.
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table = "tags")]
+pub struct TagStats {
+    pub name: String,
+    /// The reports sent with the tag.
+    pub occurrences: i64,
+    pub sessions: i64,
+    pub first_seen: i64,
+    pub last_seen: i64,
+    pub hidden: bool,
+    /// The part of the project's sessions with the tag, from 0 to 1.
+    pub session_share: f64,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TagSort {
+    /// Most occurrences first.
+    #[default]
+    Occurrences,
+    /// Most sessions first.
+    Sessions,
+    /// Most recently seen first.
+    LastSeen,
+    /// Earliest seen first.
+    FirstSeen,
+    Name,
+}
+
+/// e.g. `?prefix=checkout&sort=last_seen&include_hidden=true`.
+#[derive(Deserialize)]
+pub struct TagStatsQuery {
+    /// Keeps the tags whose name starts with it.
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub sort: TagSort,
+    #[serde(default)]
+    pub include_hidden: bool,
+}
+
+/// Renames or hides tags in the project's tag statistics and timeseries. Renaming several tags
+/// to one name, or a tag to the name of another one, merges them. Renaming a tag to its original
+/// name undoes its renaming. Sessions of the project get the new names, which tag groups match.
+#[derive(Deserialize)]
+pub struct TagUpdate {
+    /// The tags as the project shows them.
+    pub tags: Vec<String>,
+    pub name: Option<String>,
+    pub hidden: Option<bool>,
+}
+
+impl TagUpdate {
+    fn validate(&self) -> Result<(), DataError> {
+        if self.tags.is_empty() {
+            return Err(DataError::InvalidTags("no tags to update".to_string()));
+        }
+        if let Some(name) = &self.name {
+            if name.trim().is_empty() || name.trim().chars().count() > MAX_TAG_LEN {
+                return Err(DataError::InvalidTags(format!(
+                    "the name must have 1 to {} characters",
+                    MAX_TAG_LEN
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The tag statistics of the project, from the rollups of the completed days and the reports
+/// of the sessions since.
+pub async fn get_tag_stats(
+    client: &Client,
+    project_id: i32,
+    query: &TagStatsQuery,
+) -> Result<Vec<TagStats>, DataError> {
+    let days_until = rollups::days_until(client).await?;
+
+    let stmt = client
+        .prepare(include_str!("../../sql/get_tag_stats.sql"))
+        .await?;
+    let mut tag_stats = client
+        .query(
+            &stmt,
+            &[
+                &project_id,
+                &query.prefix,
+                &query.include_hidden,
+                &days_until,
+            ],
+        )
+        .await?
+        .iter()
+        .map(|row| TagStats::from_row_ref(row).unwrap())
+        .collect::<Vec<TagStats>>();
+
+    sort_tag_stats(&mut tag_stats, query.sort);
+
+    Ok(tag_stats)
+}
+
+/// Sorts by name first, the sorts by key are stable so that ties stay sorted by name.
+fn sort_tag_stats(tag_stats: &mut [TagStats], sort: TagSort) {
+    tag_stats.sort_by(|a, b| a.name.cmp(&b.name));
+    match sort {
+        TagSort::Occurrences => tag_stats.sort_by_key(|tag| Reverse(tag.occurrences)),
+        TagSort::Sessions => tag_stats.sort_by_key(|tag| Reverse(tag.sessions)),
+        TagSort::LastSeen => tag_stats.sort_by_key(|tag| Reverse(tag.last_seen)),
+        TagSort::FirstSeen => tag_stats.sort_by_key(|tag| tag.first_seen),
+        TagSort::Name => {}
+    }
+}
+
+/// Applies the update to every tag of the project's reports shown as one of `tags`.
+pub async fn update_tags(
+    client: &Client,
+    project_id: i32,
+    tag_update: &TagUpdate,
+) -> Result<(), DataError> {
+    tag_update.validate()?;
+
+    let name = tag_update.name.as_deref().map(str::trim);
+    let stmt = client
+        .prepare(include_str!("../../sql/update_project_tags.sql"))
+        .await?;
+    let updated = client
+        .execute(
+            &stmt,
+            &[&project_id, &tag_update.tags, &name, &tag_update.hidden],
+        )
+        .await?;
+
+    if updated == 0 {
+        return Err(DataError::NotFound);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag_stats(name: &str, occurrences: i64, last_seen: i64) -> TagStats {
+        TagStats {
+            name: name.to_string(),
+            occurrences,
+            sessions: 1,
+            first_seen: 0,
+            last_seen,
+            hidden: false,
+            session_share: 1.0,
+        }
+    }
+
+    fn sorted_names(mut stats: Vec<TagStats>, sort: TagSort) -> Vec<String> {
+        sort_tag_stats(&mut stats, sort);
+        stats.into_iter().map(|tag| tag.name).collect()
+    }
+
+    #[test]
+    fn ties_are_sorted_by_name() {
+        let stats = || {
+            vec![
+                tag_stats("c", 1, 10),
+                tag_stats("a", 1, 10),
+                tag_stats("d", 2, 20),
+                tag_stats("b", 1, 10),
+            ]
+        };
+
+        assert_eq!(
+            sorted_names(stats(), TagSort::Occurrences),
+            vec!["d", "a", "b", "c"]
+        );
+        assert_eq!(
+            sorted_names(stats(), TagSort::Sessions),
+            vec!["a", "b", "c", "d"]
+        );
+        assert_eq!(
+            sorted_names(stats(), TagSort::LastSeen),
+            vec!["d", "a", "b", "c"]
+        );
+        assert_eq!(
+            sorted_names(stats(), TagSort::FirstSeen),
+            vec!["a", "b", "c", "d"]
+        );
+    }
+
+    #[test]
+    fn tag_updates_need_tags_and_a_valid_name() {
+        let update = |tags: &[&str], name: Option<&str>| TagUpdate {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            name: name.map(str::to_string),
+            hidden: None,
+        };
+
+        assert!(update(&[], Some("checkout")).validate().is_err());
+        assert!(update(&["a"], Some("  ")).validate().is_err());
+        assert!(update(&["a"], Some(&"x".repeat(MAX_TAG_LEN + 1)))
+            .validate()
+            .is_err());
+        assert!(update(&["a", "b"], Some("checkout")).validate().is_ok());
+        assert!(update(&["a"], None).validate().is_ok());
+    }
+}
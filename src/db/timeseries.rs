@@ -0,0 +1,211 @@
+use crate::dberror::DataError;
+use deadpool_postgres::Client;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use tokio_pg_mapper::FromTokioPostgresRow;
+use tokio_pg_mapper_derive::PostgresMapper;
+
+/// The most buckets one query can return, an hourly series is capped to about three months.
+pub const MAX_BUCKETS: i64 = 2400;
+
+/// What is counted in each bucket, `sessions`, `reports` or `tag:<name>`.
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "String")]
+pub enum Metric {
+    /// The sessions with at least one report in the bucket.
+    Sessions,
+    Reports,
+    /// The reports sent with the tag.
+    Tag(String),
+}
+
+impl TryFrom<String> for Metric {
+    type Error = String;
+
+    fn try_from(metric: String) -> Result<Self, Self::Error> {
+        match metric.as_str() {
+            "sessions" => Ok(Metric::Sessions),
+            "reports" => Ok(Metric::Reports),
+            _ if metric.starts_with("tag:") && !metric[4..].trim().is_empty() => {
+                Ok(Metric::Tag(metric[4..].trim().to_string()))
+            }
+            _ => Err(format!(
+                "unknown metric {}, expected sessions, reports or tag:<name>",
+                metric
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Interval {
+    Hour,
+    Day,
+    /// Weeks start on Monday.
+    Week,
+}
+
+impl Interval {
+    /// The field `date_trunc` truncates to.
+    fn as_str(self) -> &'static str {
+        match self {
+            Interval::Hour => "hour",
+            Interval::Day => "day",
+            Interval::Week => "week",
+        }
+    }
+
+    /// Less than the shortest bucket, which can lose an hour, or half an hour in a few zones, to
+    /// daylight saving time.
+    fn step(self) -> &'static str {
+        match self {
+            Interval::Hour => "15 minutes",
+            Interval::Day => "1 hour",
+            Interval::Week => "1 day",
+        }
+    }
+
+    fn millis(self) -> i64 {
+        match self {
+            Interval::Hour => 60 * 60 * 1000,
+            Interval::Day => 24 * 60 * 60 * 1000,
+            Interval::Week => 7 * 24 * 60 * 60 * 1000,
+        }
+    }
+}
+
+fn utc() -> String {
+    "UTC".to_string()
+}
+
+/// A series of counts over `[from, to)`, e.g.
+/// `?metric=tag:checkout&interval=day&from=1580515200000&to=1583020800000&timezone=Europe/Paris`.
+///
+/// Buckets start at midnight, or at the hour, in `timezone`, an IANA name defaulting to UTC.
+#[derive(Deserialize)]
+pub struct TimeseriesQuery {
+    pub metric: Metric,
+    pub interval: Interval,
+    pub from: i64,
+    pub to: i64,
+    #[serde(default = "utc")]
+    pub timezone: String,
+}
+
+/// The count of a bucket, starting at `start` millisecs.
+#[derive(Serialize, PostgresMapper)]
+#[pg_mapper(table = "reports")]
+pub struct TimeseriesPoint {
+    pub start: i64,
+    pub count: i64,
+}
+
+/// Counts the metric in every bucket of the range by the client's clock, empty buckets
+/// included.
+pub async fn get_timeseries(
+    client: &Client,
+    project_id: i32,
+    query: &TimeseriesQuery,
+) -> Result<Vec<TimeseriesPoint>, DataError> {
+    if query.from >= query.to {
+        return Err(DataError::InvalidTimeseries(
+            "from must be before to".to_string(),
+        ));
+    }
+    let range = query
+        .to
+        .checked_sub(query.from)
+        .ok_or_else(|| DataError::InvalidTimeseries("the range is too long".to_string()))?;
+    if range / query.interval.millis() >= MAX_BUCKETS {
+        return Err(DataError::InvalidTimeseries(format!(
+            "a series can have at most {} buckets",
+            MAX_BUCKETS
+        )));
+    }
+
+    let stmt = client
+        .prepare(include_str!("../../sql/timezone_exists.sql"))
+        .await?;
+    let timezone_exists: bool = client
+        .query_one(&stmt, &[&query.timezone])
+        .await?
+        .get("exists");
+    if !timezone_exists {
+        return Err(DataError::InvalidTimeseries(format!(
+            "unknown time zone {}",
+            query.timezone
+        )));
+    }
+
+    let (metric, tag_name) = match &query.metric {
+        Metric::Sessions => ("sessions", None),
+        Metric::Reports => ("reports", None),
+        Metric::Tag(tag_name) => ("tag", Some(tag_name.as_str())),
+    };
+
+    let stmt = client
+        .prepare(include_str!("../../sql/get_timeseries.sql"))
+        .await?;
+    let rows = client
+        .query(
+            &stmt,
+            &[
+                &project_id,
+                &query.from,
+                &query.to,
+                &query.interval.as_str(),
+                &query.timezone,
+                &metric,
+                &tag_name,
+                &query.interval.step(),
+            ],
+        )
+        .await?;
+
+    Ok(rows
+        .iter()
+        .map(|row| TimeseriesPoint::from_row_ref(row).unwrap())
+        .collect::<Vec<TimeseriesPoint>>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metric(metric: &str) -> Result<Metric, String> {
+        Metric::try_from(metric.to_string())
+    }
+
+    #[test]
+    fn counts_are_metrics() {
+        assert!(matches!(metric("sessions"), Ok(Metric::Sessions)));
+        assert!(matches!(metric("reports"), Ok(Metric::Reports)));
+    }
+
+    #[test]
+    fn tag_metrics_are_trimmed() {
+        match metric("tag: checkout ") {
+            Ok(Metric::Tag(name)) => assert_eq!(name, "checkout"),
+            _ => panic!("tag:<name> is a tag metric"),
+        }
+        match metric("tag:tag:x") {
+            Ok(Metric::Tag(name)) => assert_eq!(name, "tag:x"),
+            _ => panic!("tag:<name> is a tag metric"),
+        }
+    }
+
+    #[test]
+    fn tag_metrics_need_a_name() {
+        assert!(metric("tag:").is_err());
+        assert!(metric("tag:   ").is_err());
+    }
+
+    #[test]
+    fn unknown_metrics_are_rejected() {
+        assert!(metric("").is_err());
+        assert!(metric("Sessions").is_err());
+        assert!(metric("tags").is_err());
+        assert!(metric("checkout").is_err());
+    }
+}
@@ -1,10 +1,12 @@
 use deadpool_postgres::Client;
 use futures::future::{ready, Ready};
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use tokio_pg_mapper::FromTokioPostgresRow;
 use tokio_pg_mapper_derive::PostgresMapper;
 
-use crate::api::users::LoginInfo;
+use crate::api::users::{LoginInfo, RegisterInfo};
+use crate::db::blocking;
 use crate::dberror::DataError;
 use actix_web::{Error, HttpRequest, HttpResponse, Responder};
 
@@ -28,18 +30,18 @@ impl Responder for User {
         let body = serde_json::to_string(&self).unwrap();
 
         ready(Ok(HttpResponse::Ok()
-            .content_type("application.json")
+            .content_type("application/json")
             .body(body)))
     }
 }
 
 impl User {
-    pub async fn get_user_by_name(client: &Client, username: String) -> Result<User, DataError> {
-        let stmt_str = include_str!("../../sql/get_user_by_username.sql");
-        let stmt = client.prepare(stmt_str).await.unwrap();
+    pub async fn get_user_by_id(client: &Client, user_id: i32) -> Result<User, DataError> {
+        let stmt_str = include_str!("../../sql/get_user_by_id.sql");
+        let stmt = client.prepare(stmt_str).await?;
 
         client
-            .query(&stmt, &[&username])
+            .query(&stmt, &[&user_id])
             .await?
             .iter()
             .map(|row| User::from_row_ref(row).unwrap())
@@ -62,19 +64,98 @@ impl User {
             .ok_or(DataError::EmailNotFound)
     }
 
+    /// Saves a new user with a hashed password, failing with `DataError::EmailTaken` if the
+    /// email is already registered.
+    pub async fn register(
+        client: &Client,
+        register_info: &RegisterInfo,
+    ) -> Result<User, DataError> {
+        let stmt_str = include_str!("../../sql/insert_user.sql");
+        let stmt = client.prepare(stmt_str).await?;
+
+        client
+            .query(
+                &stmt,
+                &[
+                    &register_info.username,
+                    &register_info.email,
+                    &hash_pass(&register_info.password).await?,
+                ],
+            )
+            .await?
+            .iter()
+            .map(|row| User::from_row_ref(row).unwrap())
+            .collect::<Vec<User>>()
+            .pop()
+            .ok_or(DataError::EmailTaken)
+    }
+
     pub async fn validate_login_info(
         client: &Client,
         login_info: &LoginInfo,
     ) -> Result<i32, DataError> {
         let user = Self::get_user_by_email(client, &login_info.email).await?;
-        if hash_pass(&login_info.password) != user.password {
-            Err(DataError::WrongPassword)
-        } else {
+
+        if is_hashed(&user.password) {
+            let is_argon2id = user.password.starts_with("$argon2id$");
+            if verify_pass(user.password, &login_info.password).await? {
+                if !is_argon2id {
+                    // hashed with argon2i before argon2id was used
+                    Self::set_password(client, user.user_id, &login_info.password).await?;
+                }
+                Ok(user.user_id)
+            } else {
+                Err(DataError::WrongPassword)
+            }
+        } else if constant_time_eq(&user.password, &login_info.password) {
+            // a plaintext password saved before hashing was introduced
+            Self::set_password(client, user.user_id, &login_info.password).await?;
             Ok(user.user_id)
+        } else {
+            Err(DataError::WrongPassword)
         }
     }
+
+    pub async fn set_password(
+        client: &Client,
+        user_id: i32,
+        password: &str,
+    ) -> Result<(), DataError> {
+        let stmt_str = include_str!("../../sql/update_password.sql");
+        let stmt = client.prepare(stmt_str).await?;
+
+        client
+            .execute(&stmt, &[&user_id, &hash_pass(password).await?])
+            .await?;
+        Ok(())
+    }
+}
+
+/// Hashes the password with argon2id and a random per-user salt, the salt and the parameters
+/// are kept in the encoded hash. Hashing takes long on purpose, so it runs on the thread pool.
+async fn hash_pass(pass: &str) -> Result<String, DataError> {
+    let pass = pass.to_string();
+    blocking(move || {
+        let salt = rand::thread_rng().gen::<[u8; 16]>();
+        let config = argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            ..argon2::Config::default()
+        };
+        Ok(argon2::hash_encoded(pass.as_bytes(), &salt, &config)?)
+    })
+    .await
+}
+
+/// Checks the password against a hash of `hash_pass`, or an older argon2i one.
+async fn verify_pass(encoded: String, pass: &str) -> Result<bool, DataError> {
+    let pass = pass.to_string();
+    blocking(move || Ok(argon2::verify_encoded(&encoded, pass.as_bytes())?)).await
+}
+
+fn is_hashed(password: &str) -> bool {
+    password.starts_with("$argon2")
 }
 
-fn hash_pass(pass: &String) -> String {
-    pass.to_owned()
+fn constant_time_eq(l: &str, r: &str) -> bool {
+    l.len() == r.len() && openssl::memcmp::eq(l.as_bytes(), r.as_bytes())
 }
@@ -1,21 +1,48 @@
+use crate::db::report_validation::ReportProblem;
 use actix_web::{HttpResponse, ResponseError};
 use deadpool_postgres::PoolError;
 use derive_more::{Display, From};
-use std::error::Error;
 use tokio_pg_mapper::Error as PGMError;
 use tokio_postgres::error::Error as PGError;
 
-pub type Result<T> = actix_web::Either<T, DataError>;
-
 #[derive(Display, From, Debug)]
 pub enum DataError {
     NotFound,
+    Unauthorized,
+    Forbidden,
     WrongPassword,
     EmailNotFound,
+    EmailTaken,
     NoSessionFound,
+    BatchTooLarge,
+    InvalidCursor,
+    #[from(ignore)]
+    InvalidFilter(String),
+    /// A tag of a report that `upsert_tags` didn't return.
+    #[from(ignore)]
+    MissingTag(String),
+    /// Every problem found with a report, answered with a 422.
+    #[from(ignore)]
+    #[display(fmt = "invalid report")]
+    InvalidReport(Vec<ReportProblem>),
+    #[from(ignore)]
+    InvalidUser(String),
+    #[from(ignore)]
+    InvalidTagGroups(String),
+    #[from(ignore)]
+    InvalidSettings(String),
+    #[from(ignore)]
+    InvalidTimeseries(String),
+    #[from(ignore)]
+    InvalidTags(String),
+    /// The schema version of the database, older than what the binary expects.
+    #[from(ignore)]
+    OutdatedSchema(i32),
     PGError(PGError),
     PGMError(PGMError),
     PoolError(PoolError),
+    HashError(argon2::Error),
+    IoError(std::io::Error),
 }
 
 impl std::error::Error for DataError {}
@@ -24,9 +51,30 @@ impl ResponseError for DataError {
     fn error_response(&self) -> HttpResponse {
         match self {
             DataError::NotFound => HttpResponse::NotFound().finish(),
+            DataError::Unauthorized => HttpResponse::Unauthorized().finish(),
+            DataError::Forbidden => HttpResponse::Forbidden().finish(),
             DataError::WrongPassword => HttpResponse::BadRequest().body("password is wrong"),
             DataError::EmailNotFound => HttpResponse::NotFound().body("Email not found"),
+            DataError::EmailTaken => HttpResponse::Conflict().body("Email is already registered"),
             DataError::NoSessionFound => HttpResponse::BadRequest().body("No session found"),
+            DataError::BatchTooLarge => HttpResponse::PayloadTooLarge().body(format!(
+                "A batch can have at most {} reports",
+                crate::db::reports::MAX_BATCH_SIZE
+            )),
+            DataError::InvalidCursor => HttpResponse::BadRequest().body("Invalid cursor"),
+            DataError::InvalidFilter(reason) => HttpResponse::BadRequest().body(reason.clone()),
+            DataError::MissingTag(_) => HttpResponse::InternalServerError().finish(),
+            DataError::InvalidReport(problems) => HttpResponse::UnprocessableEntity()
+                .content_type("application/json")
+                .body(serde_json::json!({ "problems": problems }).to_string()),
+            DataError::InvalidUser(reason) => HttpResponse::BadRequest().body(reason.clone()),
+            DataError::InvalidTagGroups(reason) => {
+                HttpResponse::BadRequest().body(reason.clone())
+            }
+            DataError::InvalidSettings(reason) => HttpResponse::BadRequest().body(reason.clone()),
+            DataError::InvalidTimeseries(reason) => HttpResponse::BadRequest().body(reason.clone()),
+            DataError::InvalidTags(reason) => HttpResponse::BadRequest().body(reason.clone()),
+            DataError::OutdatedSchema(_) => HttpResponse::InternalServerError().finish(),
             DataError::PoolError(ref err) => {
                 HttpResponse::InternalServerError().body(err.to_string())
             }
@@ -37,6 +85,12 @@ impl ResponseError for DataError {
             DataError::PGMError(err) => {
                 HttpResponse::InternalServerError().body(err.to_string())
             }
+            DataError::HashError(err) => {
+                HttpResponse::InternalServerError().body(err.to_string())
+            }
+            DataError::IoError(err) => {
+                HttpResponse::InternalServerError().body(err.to_string())
+            }
         }
     }
 }
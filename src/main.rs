@@ -5,45 +5,154 @@ mod dberror;
 
 use actix_cors::Cors;
 use actix_identity::{CookieIdentityPolicy, IdentityService};
+use actix_web::middleware::Condition;
 use actix_web::{web, App, HttpServer};
 use deadpool_postgres::config as deadpool_config;
+use dberror::DataError;
 use dotenv::dotenv;
-use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
-use postgres_openssl::MakeTlsConnector;
-use rand::Rng;
+use std::fmt::Display;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Partitions are created months ahead, checking daily leaves plenty of retries.
+const PARTITIONS_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Days are rolled up once complete, late reports for them are picked up on the next run.
+const ROLLUP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+fn database_error(err: impl Display) -> io::Error {
+    io::Error::other(format!("database error: {}", err))
+}
+
+fn invalid_config(err: impl Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid config: {}", err),
+    )
+}
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
 
-    let mut config = crate::config::Config::from_env().unwrap();
-    config.pg.ssl_mode = Some(deadpool_config::SslMode::Require);
+    let mut config = crate::config::Config::from_env().map_err(invalid_config)?;
+    config
+        .pg
+        .ssl_mode
+        .get_or_insert(deadpool_config::SslMode::Require);
 
-    let mut builder = SslConnector::builder(SslMethod::tls()).unwrap();
-    builder.set_verify(SslVerifyMode::NONE);
-    let connector = MakeTlsConnector::new(builder.build());
+    let connector = config.tls_connector().map_err(invalid_config)?;
+    let pool = config.pg.create_pool(connector).map_err(invalid_config)?;
 
-    let pool = config.pg.create_pool(connector).unwrap();
+    let mut client = pool.get().await.map_err(database_error)?;
+    if std::env::args().any(|arg| arg == "--migrate") {
+        for migration in db::migrations::migrate(&mut client)
+            .await
+            .map_err(database_error)?
+        {
+            println!("applied migration {} ({})", migration.version, migration.name);
+        }
+        return Ok(());
+    }
+    match db::migrations::check_version(&client).await {
+        Ok(()) => {}
+        Err(DataError::OutdatedSchema(version)) => {
+            return Err(database_error(format!(
+                "the schema is at version {} but {} is needed, run with --migrate",
+                version,
+                db::migrations::latest_version()
+            )))
+        }
+        Err(err) => return Err(database_error(err)),
+    }
+    db::partitions::create_partitions(&client)
+        .await
+        .map_err(database_error)?;
+    drop(client);
 
-    // let private_key = rand::thread_rng().gen::<[u8; 32]>();
-    // FIXME: Don't forget to use random key (the above line) in prod mode.
-    let private_key: [u8; 32] = [0; 32];
+    let partitions_pool = pool.clone();
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(PARTITIONS_INTERVAL);
+        loop {
+            interval.tick().await;
+            let result = match partitions_pool.get().await {
+                Ok(client) => db::partitions::create_partitions(&client).await,
+                Err(err) => Err(DataError::PoolError(err)),
+            };
+            if let Err(err) = result {
+                eprintln!("can't create the partitions of reports: {}", err);
+            }
+        }
+    });
+
+    let retention_pool = pool.clone();
+    let archive_dir = config.archive_dir.clone().map(PathBuf::from);
+    let retention_interval = Duration::from_secs(config.retention_interval_secs);
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(retention_interval);
+        loop {
+            interval.tick().await;
+            match db::retention::purge_expired_reports(&retention_pool, archive_dir.as_deref())
+                .await
+            {
+                Ok(0) => {}
+                Ok(deleted) => println!("deleted {} expired reports", deleted),
+                Err(err) => eprintln!("can't delete expired reports: {}", err),
+            }
+        }
+    });
+
+    let rollup_pool = pool.clone();
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(ROLLUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(err) = db::rollups::roll_up(&rollup_pool).await {
+                eprintln!("can't roll up reports: {}", err);
+            }
+        }
+    });
+
+    let private_key = config.cookie_key().map_err(invalid_config)?;
+    let secure_cookies = config.secure_cookies;
+    let allowed_origins = config.allowed_origins().map_err(invalid_config)?;
+
+    HttpServer::new(move || {
+        let cors = allowed_origins
+            .iter()
+            .fold(Cors::new(), |cors, origin| cors.allowed_origin(origin))
+            .supports_credentials()
+            .finish();
 
-    let server = HttpServer::new(move || {
         App::new()
-            .wrap(Cors::new().supports_credentials().finish())
+            // without any allowed origins, cross-origin requests are refused by the browser
+            .wrap(Condition::new(!allowed_origins.is_empty(), cors))
             .wrap(IdentityService::new(
                 CookieIdentityPolicy::new(&private_key)
                     .name("user_auth")
-                    .secure(false),
-            ))
-            .wrap(IdentityService::new(
-                CookieIdentityPolicy::new(&private_key)
-                    .name("report_auth")
-                    .secure(false),
+                    .secure(secure_cookies),
             ))
             .service(web::resource("/login").route(web::post().to(api::users::login)))
+            .service(web::resource("/register").route(web::post().to(api::users::register)))
+            .service(web::resource("/logout").route(web::post().to(api::users::logout)))
+            .service(
+                web::resource("/me")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::get().to(api::users::me)),
+            )
             .data(pool.clone())
+            .service(
+                web::resource("/reports")
+                    .app_data(api::reports::reports_json_config())
+                    .wrap(api::report_auth::CheckReportAuth)
+                    .route(web::post().to(api::reports::save_report)),
+            )
+            .service(
+                web::resource("/reports/batch")
+                    .app_data(api::reports::reports_json_config())
+                    .wrap(api::report_auth::CheckReportAuth)
+                    .route(web::post().to(api::reports::save_reports)),
+            )
             .service(
                 web::resource("/projects")
                     .wrap(api::user_auth::CheckLogin)
@@ -54,38 +163,107 @@ async fn main() -> std::io::Result<()> {
                     .wrap(api::user_auth::CheckLogin)
                     .route(web::post().to(api::projects::save_project)),
             )
-            // .service(api::reports::save_report)
-            // .service(
-            //     web::resource("/projects/{project_id}/sessions")
-            //         .route(web::get().to(api::reports::get_sessions)),
-            // )
-            // .service(
-            //     web::resource("/projects/{project_id}/grouped")
-            //         .route(web::get().to(api::reports::get_grouped_sessions)),
-            // )
-            // .service(
-            //     web::resource("/projects/{access_key}/session-counts")
-            //         .route(web::get().to(api::projects::get_project_sessions_count)),
-            // )
-            // .service(
-            //     web::resource("/projects/{access_key}/avg-duration")
-            //         .route(web::get().to(api::projects::get_average_session_duration)),
-            // )
-            // .service(
-            //     web::resource("/projects/{access_key}/tags")
-            //         .route(web::get().to(api::projects::get_project_tags)),
-            // )
-            // .service(
-            //     web::resource("/projects/{project_id}/percentages")
-            //         .route(web::post().to(api::reports::get_percentages)),
-            // )
-            // .service(
-            //     web::resource("/projects/{project_id}/analysis")
-            //         .route(web::post().to(api::reports::get_sessions_analysis)),
-            // )
+            .service(
+                web::resource("/projects/{project_id}/sessions")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::get().to(api::reports::get_sessions)),
+            )
+            .service(
+                web::resource("/projects/{project_id}/sessions/{session_id}")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::delete().to(api::reports::purge_session)),
+            )
+            .service(
+                web::resource("/projects/{project_id}/retention")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::put().to(api::projects::set_retention)),
+            )
+            .service(
+                web::resource("/projects/{project_id}/grouped")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::get().to(api::reports::get_grouped_sessions)),
+            )
+            .service(
+                web::resource("/projects/{access_key}/session-counts")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::get().to(api::projects::get_project_sessions_count)),
+            )
+            .service(
+                web::resource("/projects/{access_key}/avg-duration")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::get().to(api::projects::get_average_session_duration)),
+            )
+            .service(
+                web::resource("/projects/{access_key}/duration-stats")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::get().to(api::projects::get_session_duration_stats)),
+            )
+            .service(
+                web::resource("/projects/{project_id:\\d+}/tags")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::get().to(api::tags::get_tag_stats))
+                    .route(web::put().to(api::tags::update_tags)),
+            )
+            .service(
+                web::resource("/projects/{access_key}/tags")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::get().to(api::projects::get_project_tags)),
+            )
+            .service(
+                web::resource("/projects/{access_key}/ingest-secret")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::post().to(api::projects::rotate_ingest_secret))
+                    .route(web::delete().to(api::projects::remove_ingest_secret)),
+            )
+            .service(
+                web::resource("/projects/{access_key}/revoke")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::post().to(api::projects::revoke_access_key)),
+            )
+            .service(
+                web::resource("/projects/{project_id}/percentages")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::post().to(api::reports::get_percentages)),
+            )
+            .service(
+                web::resource("/projects/{project_id}/analysis")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::post().to(api::reports::get_sessions_analysis)),
+            )
+            .service(
+                web::resource("/projects/{project_id}/steps/{step_number}")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::post().to(api::reports::get_step_frequencies)),
+            )
+            .service(
+                web::resource("/projects/{project_id}/funnel")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::post().to(api::reports::get_funnel_analysis)),
+            )
+            .service(
+                web::resource("/projects/{project_id}/flow")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::post().to(api::reports::get_flow_analysis)),
+            )
+            .service(
+                web::resource("/projects/{project_id}/timeseries")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::get().to(api::reports::get_project_timeseries)),
+            )
+            .service(
+                web::resource("/projects/{project_id}/tag-groups")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::get().to(api::tag_groups::get_tag_groups))
+                    .route(web::post().to(api::tag_groups::save_tag_group)),
+            )
+            .service(
+                web::resource("/projects/{project_id}/tag-groups/{tag_group_id}")
+                    .wrap(api::user_auth::CheckLogin)
+                    .route(web::put().to(api::tag_groups::update_tag_group))
+                    .route(web::delete().to(api::tag_groups::delete_tag_group)),
+            )
     })
-    .bind("127.0.0.1:9000")?
+    .bind(&config.server_addr)?
     .run()
-    .await;
-    Ok(())
+    .await
 }
@@ -1,6 +1,4 @@
 export async function getPercentages(tagGroups, projectId) {
-  // FIXME:
-  projectId = 10;
   tagGroups = tagGroups.groups.map((g, i) => {
     return {
       id: i,
@@ -20,8 +18,6 @@ export async function getPercentages(tagGroups, projectId) {
 }
 
 export async function getSessionsAnalysis(tagGroups, projectId) {
-  // FIXME:
-  projectId = 10;
   tagGroups = tagGroups.groups.map((g, i) => {
     return {
       id: i,
@@ -19,7 +19,7 @@ import Backdrop from "@material-ui/core/Backdrop";
 import CircularProgress from "@material-ui/core/CircularProgress";
 import Divider from "@material-ui/core/Divider";
 
-export function Analytics({ accessKey }) {
+export function Analytics({ accessKey, projectId }) {
   const [modalOpen, setModalOpen] = useState(false);
   let [tab, setTab] = useState(0);
   let [percentagesResult, setPercentagesResult] = useState(null);
@@ -41,10 +41,10 @@ export function Analytics({ accessKey }) {
     setQuery(qry);
     setLoading(true);
     if (tab === 0) {
-      let percentages = await getPercentages(qry);
+      let percentages = await getPercentages(qry, projectId);
       setPercentagesResult(percentages);
     } else {
-      let analysisResult = await getSessionsAnalysis(qry);
+      let analysisResult = await getSessionsAnalysis(qry, projectId);
       setanalysisResult(analysisResult);
     }
     setLoading(false);
@@ -1,5 +1,5 @@
 import React from "react";
-import { useParams, useHistory } from "react-router-dom";
+import { useParams, useHistory, useLocation } from "react-router-dom";
 import TopAppBar from "./TopAppBar";
 import { useQuery } from "react-query";
 import {
@@ -25,6 +25,7 @@ export function LoadingBar({ isLoading }) {
 export default function Project() {
   let history = useHistory();
   let { name, accessKey } = useParams();
+  let { projectId } = useLocation().state || {};
   let { isLoading: isDurationLoading, data: avgDuration } = useQuery(name, () =>
     fetchAverageSessionDuration(accessKey)
   );
@@ -40,7 +41,7 @@ export default function Project() {
       />
       <TopAppBar pageName={name} />
       <Overview accessKey={accessKey} avgDuration={avgDuration} sessionsCount={sessionsCount} />
-      <Analytics accessKey={accessKey} />
+      <Analytics accessKey={accessKey} projectId={projectId} />
     </React.Fragment>
   );
 }
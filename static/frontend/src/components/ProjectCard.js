@@ -10,7 +10,7 @@ import { useQuery } from "react-query";
 import { fetchAverageSessionDuration } from "../api/projects";
 import Project from "./Project";
 
-export default function ProjectCard({ name, sessions, accessKey }) {
+export default function ProjectCard({ name, sessions, accessKey, projectId }) {
   return (
     <Card
       style={{
@@ -27,7 +27,7 @@ export default function ProjectCard({ name, sessions, accessKey }) {
       </CardContent>
       <CardActions>
         <Link
-          to={{ pathname: `/projects/${name}/${accessKey}`, state: {accessKey, name} }}
+          to={{ pathname: `/projects/${name}/${accessKey}`, state: {accessKey, name, projectId} }}
           style={{ textDecoration: "none" }}
         >
           <Button color={"default"} size={"small"}>
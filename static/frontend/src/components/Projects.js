@@ -72,6 +72,7 @@ function ProjectsGrid({ projects }) {
           name={p.name}
           sessions={p.sessions}
           accessKey={p.access_key}
+          projectId={p.project_id}
         />
       ))}
     </div>